@@ -1,5 +1,64 @@
+use prost_build::Config;
+use prost_types::FileDescriptorSet;
+use prost_types::field_descriptor_proto::{Label, Type};
 use std::io::Result;
+
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["src/Protocol.proto"], &["src/"])?;
+    let mut config = Config::new();
+    let fds = config.load_fds(&["src/Protocol.proto"], &["src/"])?;
+
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        derive_serde(&mut config, &fds);
+    }
+
+    config.compile_fds(fds)?;
     Ok(())
 }
+
+// enums, including enum fields which prost keeps as i32, go by their proto
+// names so the JSON reads like Protocol.proto
+fn derive_serde(config: &mut Config, fds: &FileDescriptorSet) {
+    config
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".", "#[serde(default)]");
+
+    for file in &fds.file {
+        let package = format!(".{}", file.package());
+
+        for enumeration in &file.enum_type {
+            for value in &enumeration.value {
+                config.field_attribute(
+                    format!("{}.{}.{}", package, enumeration.name(), value.name()),
+                    format!("#[serde(rename = \"{}\")]", value.name()),
+                );
+            }
+        }
+
+        for message in &file.message_type {
+            for field in &message.field {
+                let path = format!("{}.{}.{}", package, message.name(), field.name());
+
+                if field.r#type() == Type::Enum {
+                    let name = field.type_name().rsplit('.').next().unwrap_or_default();
+                    let helper = match field.label() {
+                        Label::Repeated => "repeated_enum",
+                        _ => "optional_enum",
+                    };
+                    config.field_attribute(
+                        &path,
+                        format!(
+                            "#[serde(serialize_with = \"crate::messages::{helper}::serialize::<crate::messages::{name}, _>\", deserialize_with = \"crate::messages::{helper}::deserialize::<crate::messages::{name}, _>\")]"
+                        ),
+                    );
+                }
+
+                if field.label() == Label::Optional {
+                    config.field_attribute(
+                        &path,
+                        "#[serde(skip_serializing_if = \"Option::is_none\")]",
+                    );
+                }
+            }
+        }
+    }
+}
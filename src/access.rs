@@ -0,0 +1,124 @@
+use crate::QuestDevice;
+use crate::com::oculus::companion::server::{Method, PinUnlockRequest, PinUnlockResponse};
+use crate::error::HzospalError;
+use crate::protocol::{decoder::receive_protobuf, encoder::send_on_session};
+use crate::session::Session;
+use futures::future::BoxFuture;
+use log::*;
+use std::fmt;
+use std::sync::Arc;
+
+// wrong PINs before we stop asking, the headset has its own limit on top
+pub const MAX_PIN_ATTEMPTS: u32 = 3;
+
+// settings the organisation owns on a managed headset, refused before they're
+// sent rather than failing with whatever the headset answers
+const MANAGED_RESTRICTED: &[Method] = &[
+    Method::OculusSetUserSecret,
+    Method::OculusLogout,
+    Method::OculusSetAccessToken,
+    Method::MetaSetAccessToken,
+    Method::MetaSetAccessTokenCombined,
+    Method::PinSet,
+    Method::PinReset,
+    Method::WipeData,
+    Method::DevModeSet,
+    Method::AdbModeSet,
+    Method::MtpModeSet,
+    Method::OtaEnabledSet,
+    Method::ManagedModeSet,
+    Method::NuxCompleted,
+    Method::RetailSkipFirstTimeNux,
+];
+
+// what the headset said about itself in its HelloResponse
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    // PIN locked, nothing but PinUnlock works until it's unlocked
+    pub device_needs_to_be_unlocked: bool,
+    // enrolled with an organisation, see allowed_on_managed
+    pub is_device_managed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PinAttempt {
+    pub headset: String,
+    // starts at 1, anything higher means the last PIN was wrong
+    pub attempt: u32,
+}
+
+// asked for the PIN whenever a locked headset has to be unlocked, reconnects
+// included, None gives up
+#[derive(Clone)]
+pub struct PinPrompt(
+    pub Arc<dyn Fn(PinAttempt) -> BoxFuture<'static, Option<String>> + Send + Sync>,
+);
+
+impl PinPrompt {
+    pub fn new(
+        prompt: impl Fn(PinAttempt) -> BoxFuture<'static, Option<String>> + Send + Sync + 'static,
+    ) -> Self {
+        PinPrompt(Arc::new(prompt))
+    }
+}
+
+impl fmt::Debug for PinPrompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PinPrompt")
+    }
+}
+
+pub fn allowed_on_managed(method: Method) -> bool {
+    !MANAGED_RESTRICTED.contains(&method)
+}
+
+impl QuestDevice {
+    pub fn handshake(&self) -> HandshakeInfo {
+        *self.current_session().handshake.lock().unwrap()
+    }
+}
+
+// runs between hello and authentication, asking pin_prompt until the headset
+// takes a PIN
+pub(crate) async fn unlock(quest: &QuestDevice, session: &Session) -> Result<(), HzospalError> {
+    let prompt = quest.pin_prompt.as_ref().ok_or_else(|| {
+        HzospalError::Locked("Headset is PIN locked and no PIN prompt was given".into())
+    })?;
+
+    for attempt in 1..=MAX_PIN_ATTEMPTS {
+        let pin = (prompt.0)(PinAttempt {
+            headset: quest.name.clone(),
+            attempt,
+        })
+        .await
+        .ok_or_else(|| HzospalError::Locked("No PIN was given".into()))?;
+
+        let unlock_req = PinUnlockRequest { pin: Some(pin) };
+        let pending = send_on_session(quest, session, Some(unlock_req), Method::PinUnlock).await?;
+        let response = receive_protobuf::<PinUnlockResponse>(pending, quest.timeouts.rpc).await?;
+
+        if response.correct() {
+            debug!("Unlocked {}", quest.name);
+            session
+                .handshake
+                .lock()
+                .unwrap()
+                .device_needs_to_be_unlocked = false;
+            return Ok(());
+        }
+
+        if let Some(cooldown) = response.cooldown_timeout.filter(|c| *c > 0) {
+            return Err(HzospalError::Locked(format!(
+                "Wrong PIN, the headset accepts another one in {} seconds",
+                cooldown
+            )));
+        }
+
+        warn!("Wrong PIN for {} (attempt {})", quest.name, attempt);
+    }
+
+    Err(HzospalError::Locked(format!(
+        "Wrong PIN {} times in a row",
+        MAX_PIN_ATTEMPTS
+    )))
+}
@@ -0,0 +1,163 @@
+use crate::error::HzospalError;
+use crate::scan::wildcard_match;
+use btleplug::api::{BDAddr, Central, Manager as _, Peripheral as _};
+use btleplug::platform::{Adapter, Manager};
+use std::convert::Infallible;
+use std::str::FromStr;
+
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    // position in list_adapters, stable while no adapter is plugged in or out
+    pub index: usize,
+    // whatever the platform reports, on BlueZ "hci0 (usb:v1D6Bp0246d0540)"
+    pub info: String,
+    pub address: Option<BDAddr>,
+}
+
+impl AdapterInfo {
+    // the first word of info, e.g. hci0
+    pub fn name(&self) -> &str {
+        self.info.split_whitespace().next().unwrap_or(&self.info)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum AdapterSelector {
+    #[default]
+    First,
+    Index(usize),
+    Address(BDAddr),
+    // case-insensitive, * and ? wildcards, matched against the name and the
+    // full info string
+    Name(String),
+    // scan on every adapter and connect through the least busy one that can
+    // see the headset
+    All,
+}
+
+impl AdapterSelector {
+    pub fn matches(&self, adapter: &AdapterInfo) -> bool {
+        match self {
+            AdapterSelector::First => adapter.index == 0,
+            AdapterSelector::Index(index) => adapter.index == *index,
+            AdapterSelector::Address(address) => adapter.address.as_ref() == Some(address),
+            AdapterSelector::Name(pattern) => {
+                wildcard_match(pattern, adapter.name()) || wildcard_match(pattern, &adapter.info)
+            }
+            AdapterSelector::All => true,
+        }
+    }
+}
+
+// "all", an index, an address or a name pattern, in that order
+impl FromStr for AdapterSelector {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("all") {
+            Ok(AdapterSelector::All)
+        } else if let Ok(index) = s.parse() {
+            Ok(AdapterSelector::Index(index))
+        } else if let Ok(address) = s.parse() {
+            Ok(AdapterSelector::Address(address))
+        } else {
+            Ok(AdapterSelector::Name(s.to_string()))
+        }
+    }
+}
+
+async fn adapters_with_info() -> Result<Vec<(AdapterInfo, Adapter)>, HzospalError> {
+    let manager = Manager::new().await?;
+    let adapters = manager.adapters().await?;
+    let addresses = adapter_addresses().await;
+
+    let mut result = Vec::with_capacity(adapters.len());
+    for (index, adapter) in adapters.into_iter().enumerate() {
+        let info = adapter.adapter_info().await?;
+        let address = info
+            .split_whitespace()
+            .next()
+            .and_then(|name| addresses.iter().find(|(n, _)| n == name))
+            .map(|(_, address)| *address);
+
+        result.push((
+            AdapterInfo {
+                index,
+                info,
+                address,
+            },
+            adapter,
+        ));
+    }
+
+    Ok(result)
+}
+
+pub async fn list_adapters() -> Result<Vec<AdapterInfo>, HzospalError> {
+    Ok(adapters_with_info()
+        .await?
+        .into_iter()
+        .map(|(info, _)| info)
+        .collect())
+}
+
+pub(crate) async fn select_adapters(
+    selector: &AdapterSelector,
+) -> Result<Vec<(AdapterInfo, Adapter)>, HzospalError> {
+    let adapters: Vec<_> = adapters_with_info()
+        .await?
+        .into_iter()
+        .filter(|(info, _)| selector.matches(info))
+        .collect();
+
+    if adapters.is_empty() {
+        return Err(HzospalError::transport(format!(
+            "No Bluetooth adapter matching {:?}",
+            selector
+        )));
+    }
+
+    Ok(adapters)
+}
+
+// number of peripherals an adapter currently holds a connection to
+pub(crate) async fn connection_count(adapter: &Adapter) -> usize {
+    let mut count = 0;
+    for peripheral in adapter.peripherals().await.unwrap_or_default() {
+        if peripheral.is_connected().await.unwrap_or(false) {
+            count += 1;
+        }
+    }
+    count
+}
+
+// btleplug doesn't expose adapter addresses, BlueZ does
+#[cfg(target_os = "linux")]
+async fn adapter_addresses() -> Vec<(String, BDAddr)> {
+    let Ok((resource, session)) = bluez_async::BluetoothSession::new().await else {
+        return Vec::new();
+    };
+    let resource = tokio::spawn(resource);
+
+    let addresses = session
+        .get_adapters()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|a| {
+            (
+                a.id.to_string(),
+                BDAddr::from(<[u8; 6]>::from(a.mac_address)),
+            )
+        })
+        .collect();
+
+    resource.abort();
+
+    addresses
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn adapter_addresses() -> Vec<(String, BDAddr)> {
+    Vec::new()
+}
@@ -0,0 +1,252 @@
+use crate::QuestDevice;
+use crate::com::oculus::companion::server::{
+    ErrorCode, HmdCapabilitiesResponse, HmdVersionResponse, Method,
+};
+use crate::error::HzospalError;
+use crate::protocol::rpc::{self, MethodVisitor, QuestRpc, visit};
+use futures::future::BoxFuture;
+use log::*;
+use std::collections::HashMap;
+
+// read-only methods that take no arguments, asking them changes nothing on
+// the headset
+const PROBES: &[Method] = &[
+    Method::HmdStatus,
+    Method::HmdExternalBatteryStatus,
+    Method::WifiStatus,
+    Method::ControllerStatus,
+    Method::PinStatus,
+    Method::PinReauthStatus,
+    Method::GetOtaStatus,
+    Method::DevModeStatus,
+    Method::MtpModeStatus,
+    Method::AdbModeStatus,
+    Method::OtaEnabledStatus,
+    Method::CrashReportsEnabledStatus,
+    Method::AutowakeStatus,
+    Method::AutosleepTimeStatus,
+    Method::LineFrequencyStatus,
+    Method::ManagedModeStatus,
+    Method::CastingStatus,
+    Method::CastingDialogStatus,
+    Method::DevelopmentLicenseDetails,
+    Method::PhoneNotificationStatus,
+    Method::PassAndTryStatus,
+    Method::ManagedDeviceStatus,
+    Method::ManagedAutoProvisioningStatus,
+    Method::ManagedAutoProvisioningAppsStatus,
+    Method::DeviceGenerationId,
+];
+
+// a setting the firmware can't report can't be changed either
+const SETTERS: &[(Method, Method)] = &[
+    (Method::DevModeSet, Method::DevModeStatus),
+    (Method::MtpModeSet, Method::MtpModeStatus),
+    (Method::AdbModeSet, Method::AdbModeStatus),
+    (Method::OtaEnabledSet, Method::OtaEnabledStatus),
+    (
+        Method::CrashReportsEnabledSet,
+        Method::CrashReportsEnabledStatus,
+    ),
+    (Method::AutowakeSet, Method::AutowakeStatus),
+    (Method::AutosleepTimeSet, Method::AutosleepTimeStatus),
+    (Method::LineFrequencySet, Method::LineFrequencyStatus),
+    (
+        Method::PhoneNotificationSet,
+        Method::PhoneNotificationStatus,
+    ),
+    (Method::ManagedModeSet, Method::ManagedModeStatus),
+];
+
+const CASTING: &[Method] = &[
+    Method::DiscoverCastingDevices,
+    Method::StartCasting,
+    Method::StopCasting,
+    Method::CastingStatus,
+    Method::CastingDialogStatus,
+];
+
+const MULTI_USER: &[Method] = &[
+    Method::OculusSecondaryAccountLogin,
+    Method::OculusSecondaryUserAdd,
+    Method::OculusSecondaryUserRemove,
+    Method::OculusSecondaryUserGetDeviceCode,
+    Method::MetaSecondaryAccountLogin,
+    Method::SecondaryUserSupportedMethodsResponse,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Support {
+    Supported,
+    // the headset answered UNSUPPORTED_METHOD or UPDATE_REQUIRED
+    Unsupported(ErrorCode),
+    // HMD_CAPABILITIES says the feature isn't there
+    Unavailable,
+}
+
+// what one firmware build answers, only valid while version_incremental
+// stays the same
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub version: HmdVersionResponse,
+    // None if the firmware doesn't answer HMD_CAPABILITIES either
+    pub hmd: Option<HmdCapabilitiesResponse>,
+    pub methods: HashMap<Method, Support>,
+}
+
+impl Capabilities {
+    pub fn version_incremental(&self) -> &str {
+        self.version.version_incremental()
+    }
+
+    // None if nothing we asked says either way
+    pub fn supports(&self, method: Method) -> Option<bool> {
+        self.methods.get(&method).map(|s| *s == Support::Supported)
+    }
+
+    pub(crate) fn check(&self, method: Method) -> Result<(), HzospalError> {
+        if self.supports(method) == Some(false) {
+            return Err(HzospalError::Unsupported(format!(
+                "{:?} on build {}",
+                method,
+                self.version_incremental()
+            )));
+        }
+        Ok(())
+    }
+
+    fn infer(&mut self) {
+        for (set, status) in SETTERS {
+            if let Some(Support::Unsupported(code)) = self.methods.get(status).copied() {
+                self.methods
+                    .entry(*set)
+                    .or_insert(Support::Unsupported(code));
+            }
+        }
+
+        let Some(hmd) = &self.hmd else {
+            return;
+        };
+        let features = [
+            (hmd.chromecast_available, CASTING),
+            (hmd.multi_user_available, MULTI_USER),
+        ];
+        for (available, methods) in features {
+            if available == Some(false) {
+                for method in methods {
+                    self.methods.entry(*method).or_insert(Support::Unavailable);
+                }
+            }
+        }
+    }
+}
+
+// whether the headset knows the method at all, failing for any other reason
+// still means it does
+fn support<T>(result: &Result<T, HzospalError>) -> Option<Support> {
+    match result {
+        Ok(_) => Some(Support::Supported),
+        Err(e) => match e.error_code()? {
+            code @ (ErrorCode::UnsupportedMethod | ErrorCode::UpdateRequired) => {
+                Some(Support::Unsupported(code))
+            }
+            _ => Some(Support::Supported),
+        },
+    }
+}
+
+struct Probe<'a> {
+    quest: &'a QuestDevice,
+}
+
+impl<'a> MethodVisitor for Probe<'a> {
+    type Output = BoxFuture<'a, Result<(), HzospalError>>;
+
+    fn visit<M: QuestRpc>(self) -> Self::Output {
+        Box::pin(async move { self.quest.call::<M>(Default::default()).await.map(|_| ()) })
+    }
+}
+
+impl QuestDevice {
+    // what probe_capabilities found, wrappers refuse methods it says the
+    // firmware lacks without asking the headset
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities.lock().unwrap().clone()
+    }
+
+    // asks HMD_VERSION, HMD_CAPABILITIES and every status method, only
+    // HMD_VERSION if the build is the one we already probed
+    pub async fn probe_capabilities(&self) -> Result<Capabilities, HzospalError> {
+        let version = self.call::<rpc::HmdVersion>(()).await?;
+
+        if let Some(cached) = self.capabilities()
+            && cached.version.version_incremental == version.version_incremental
+        {
+            debug!(
+                "Capabilities of build {} already known",
+                cached.version_incremental()
+            );
+            return Ok(cached);
+        }
+
+        // a new build, nothing we knew about the old one holds
+        *self.capabilities.lock().unwrap() = None;
+        debug!(
+            "Probing capabilities of build {}",
+            version.version_incremental()
+        );
+
+        let hmd = self.call::<rpc::HmdCapabilities>(()).await;
+        let mut methods = HashMap::from([(Method::HmdVersion, Support::Supported)]);
+        if let Some(support) = support(&hmd) {
+            methods.insert(Method::HmdCapabilities, support);
+        }
+        let hmd = match hmd {
+            Ok(hmd) => Some(hmd),
+            Err(e) if e.error_code().is_some() => None,
+            Err(e) => return Err(e),
+        };
+
+        for method in PROBES {
+            let Some(probe) = visit(*method, Probe { quest: self }) else {
+                continue;
+            };
+            let result = probe.await;
+            match support(&result) {
+                Some(support) => {
+                    debug!("{:?}: {:?}", method, support);
+                    methods.insert(*method, support);
+                }
+                // the link or a timeout failed us, that says nothing about
+                // the firmware
+                None => return Err(result.unwrap_err()),
+            }
+        }
+
+        let mut capabilities = Capabilities {
+            version,
+            hmd,
+            methods,
+        };
+        capabilities.infer();
+
+        *self.capabilities.lock().unwrap() = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    pub(crate) fn check_supported(&self, method: Method) -> Result<(), HzospalError> {
+        match &*self.capabilities.lock().unwrap() {
+            Some(capabilities) => capabilities.check(method),
+            None => Ok(()),
+        }
+    }
+
+    // so the next call fails fast, only once the build was probed
+    pub(crate) fn record_support<T>(&self, method: Method, result: &Result<T, HzospalError>) {
+        if let Some(capabilities) = &mut *self.capabilities.lock().unwrap()
+            && let Some(support) = support(result)
+        {
+            capabilities.methods.insert(method, support);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::com::oculus::companion::server::ErrorDetails;
+
+pub use crate::com::oculus::companion::server::{ErrorCode, ResponseCode};
+use std::error::Error;
+use std::fmt;
+
+// a FAIL or FAIL_RETRY response, with the ErrorDetails the headset sent
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceError {
+    pub response_code: ResponseCode,
+    pub code: ErrorCode,
+    pub debug_details: Option<String>,
+    pub localized_user_facing_description: Option<String>,
+    pub ble_pairing_key: Option<i32>,
+}
+
+impl DeviceError {
+    pub(crate) fn from_details(response_code: ResponseCode, details: ErrorDetails) -> Self {
+        Self {
+            response_code,
+            code: details.code(),
+            debug_details: details.debug_details,
+            localized_user_facing_description: details.localized_user_facing_description,
+            ble_pairing_key: details.ble_pairing_key.and_then(|k| k.key),
+        }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "device returned {:?}", self.code)?;
+
+        if let Some(details) = &self.debug_details {
+            write!(f, ": {}", details)?;
+        }
+        if let Some(description) = &self.localized_user_facing_description {
+            write!(f, " ({})", description)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum HzospalError {
+    // the headset answered, but with a failure
+    Device(DeviceError),
+    // the link itself failed, Bluetooth or otherwise
+    Transport(Box<dyn Error + Send + Sync>),
+    NotConnected,
+    Crypto(String),
+    // the headset couldn't prove who it is
+    Certificate(String),
+    Framing(String),
+    Timeout(String),
+    Decode(prost::DecodeError),
+    Encode(prost::EncodeError),
+    // the headset answered with something that doesn't follow the protocol
+    Protocol(String),
+    Keystore(String),
+    // PIN locked and we couldn't unlock it
+    Locked(String),
+    // the organisation managing the headset owns what we tried to change
+    Managed(String),
+    // what we were asked to send doesn't make a valid request
+    BadRequest(String),
+    // probe_capabilities found the firmware doesn't do this
+    Unsupported(String),
+}
+
+impl HzospalError {
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            HzospalError::Device(e) => Some(e.code),
+            _ => None,
+        }
+    }
+
+    pub fn transport(e: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        HzospalError::Transport(e.into())
+    }
+}
+
+impl fmt::Display for HzospalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HzospalError::Device(e) => e.fmt(f),
+            HzospalError::Transport(e) => write!(f, "transport error: {}", e),
+            HzospalError::NotConnected => write!(f, "device is not connected"),
+            HzospalError::Crypto(msg) => write!(f, "crypto error: {}", msg),
+            HzospalError::Certificate(msg) => write!(f, "untrusted headset: {}", msg),
+            HzospalError::Framing(msg) => write!(f, "framing error: {}", msg),
+            HzospalError::Timeout(msg) => write!(f, "timed out: {}", msg),
+            HzospalError::Decode(e) => write!(f, "failed to decode protobuf: {}", e),
+            HzospalError::Encode(e) => write!(f, "failed to encode protobuf: {}", e),
+            HzospalError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            HzospalError::Keystore(msg) => write!(f, "key store error: {}", msg),
+            HzospalError::Locked(msg) => write!(f, "headset is locked: {}", msg),
+            HzospalError::Managed(msg) => write!(f, "headset is managed: {}", msg),
+            HzospalError::BadRequest(msg) => write!(f, "bad request: {}", msg),
+            HzospalError::Unsupported(msg) => write!(f, "not supported on this firmware: {}", msg),
+        }
+    }
+}
+
+impl Error for HzospalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HzospalError::Transport(e) => Some(e.as_ref()),
+            HzospalError::Decode(e) => Some(e),
+            HzospalError::Encode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DeviceError> for HzospalError {
+    fn from(e: DeviceError) -> Self {
+        HzospalError::Device(e)
+    }
+}
+
+impl From<btleplug::Error> for HzospalError {
+    fn from(e: btleplug::Error) -> Self {
+        match e {
+            // the link dropped, retry and the dispatcher tell this apart
+            btleplug::Error::NotConnected => HzospalError::NotConnected,
+            e => HzospalError::Transport(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<bluez_async::BluetoothError> for HzospalError {
+    fn from(e: bluez_async::BluetoothError) -> Self {
+        HzospalError::Transport(Box::new(e))
+    }
+}
+
+impl From<prost::DecodeError> for HzospalError {
+    fn from(e: prost::DecodeError) -> Self {
+        HzospalError::Decode(e)
+    }
+}
+
+impl From<prost::EncodeError> for HzospalError {
+    fn from(e: prost::EncodeError) -> Self {
+        HzospalError::Encode(e)
+    }
+}
+
+impl From<prost::UnknownEnumValue> for HzospalError {
+    fn from(e: prost::UnknownEnumValue) -> Self {
+        HzospalError::Protocol(e.to_string())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::QuestDevice;
+use crate::protocol::functions::ping;
+use log::*;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+#[derive(Clone, Debug)]
+pub struct KeepalivePolicy {
+    pub interval: Duration,
+    // a ping that takes longer than this counts as failed
+    pub timeout: Duration,
+    // consecutive failures before the link is flagged unhealthy
+    pub unhealthy_after: u32,
+    // consecutive failures before the link is torn down and re-established,
+    // None only flags it
+    pub reconnect_after: Option<u32>,
+}
+
+impl Default for KeepalivePolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(5),
+            unhealthy_after: 2,
+            reconnect_after: Some(3),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkHealth {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_rtt: Option<Duration>,
+    pub last_success: Option<Instant>,
+    pub last_failure: Option<String>,
+}
+
+impl Default for LinkHealth {
+    fn default() -> Self {
+        Self {
+            healthy: true,
+            consecutive_failures: 0,
+            last_rtt: None,
+            last_success: None,
+            last_failure: None,
+        }
+    }
+}
+
+impl QuestDevice {
+    pub fn health(&self) -> LinkHealth {
+        self.health.lock().unwrap().clone()
+    }
+}
+
+// pings until dropped, or until the QuestDevice itself is dropped
+pub struct Keepalive(JoinHandle<()>);
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+pub fn spawn_keepalive(quest: &Arc<QuestDevice>, policy: KeepalivePolicy) -> Keepalive {
+    Keepalive(tokio::spawn(run(Arc::downgrade(quest), policy)))
+}
+
+async fn run(quest: Weak<QuestDevice>, policy: KeepalivePolicy) {
+    let mut interval = tokio::time::interval(policy.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // the first tick fires immediately, the link was just set up
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let Some(quest) = quest.upgrade() else {
+            return;
+        };
+
+        let failures = match ping(&quest, policy.timeout).await {
+            Ok(rtt) => {
+                debug!("Ping to {} took {:?}", quest.name, rtt);
+                continue;
+            }
+            Err(e) => {
+                let health = quest.health();
+                warn!(
+                    "Ping to {} failed ({}), {} in a row",
+                    quest.name, e, health.consecutive_failures
+                );
+                health.consecutive_failures
+            }
+        };
+
+        if failures >= policy.unhealthy_after {
+            quest.health.lock().unwrap().healthy = false;
+        }
+
+        if let Some(reconnect_after) = policy.reconnect_after
+            && failures >= reconnect_after
+            && let Err(e) = quest.reconnect().await
+        {
+            warn!("Keepalive reconnect to {} failed: {}", quest.name, e);
+        }
+    }
+}
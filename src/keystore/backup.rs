@@ -0,0 +1,201 @@
+use super::{HeadsetIdentity, KeyEntry, now};
+use crate::error::HzospalError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hex::FromHex;
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+const BEGIN: &str = "-----BEGIN HZOSPAL DEVICE KEY-----";
+const END: &str = "-----END HZOSPAL DEVICE KEY-----";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyFormat {
+    Hex,
+    Base64,
+    // the key and who it belongs to, with a checksum to catch typos
+    #[default]
+    Armored,
+    // the armored block as a QR code drawn with unicode blocks, export only
+    Qr,
+}
+
+impl FromStr for KeyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(KeyFormat::Hex),
+            "base64" => Ok(KeyFormat::Base64),
+            "armored" | "armor" => Ok(KeyFormat::Armored),
+            "qr" => Ok(KeyFormat::Qr),
+            _ => Err(format!(
+                "unknown key format {:?}, expected hex, base64, armored or qr",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyFormat::Hex => "hex",
+            KeyFormat::Base64 => "base64",
+            KeyFormat::Armored => "armored",
+            KeyFormat::Qr => "qr",
+        })
+    }
+}
+
+impl KeyEntry {
+    pub fn export(&self, format: KeyFormat) -> Result<String, HzospalError> {
+        match format {
+            KeyFormat::Hex => Ok(hex::encode(self.user_secret)),
+            KeyFormat::Base64 => Ok(BASE64.encode(self.user_secret)),
+            KeyFormat::Armored => Ok(self.armored()),
+            KeyFormat::Qr => {
+                let code = QrCode::new(self.armored()).map_err(|e| {
+                    HzospalError::Keystore(format!("Can't fit the key in a QR code: {}", e))
+                })?;
+                // light on dark, as terminals usually are
+                Ok(code
+                    .render::<Dense1x2>()
+                    .dark_color(Dense1x2::Light)
+                    .light_color(Dense1x2::Dark)
+                    .build())
+            }
+        }
+    }
+
+    fn armored(&self) -> String {
+        let mut text = format!("{}\n", BEGIN);
+        for (name, value) in self.headers() {
+            text.push_str(&format!("{}: {}\n", name, value));
+        }
+        text.push_str(&format!("Checksum: {}\n\n", self.checksum()));
+        text.push_str(&format!("{}\n{}\n", BASE64.encode(self.user_secret), END));
+        text
+    }
+
+    // in the order they're written, the checksum covers them in this order
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        let identity = &self.identity;
+        [
+            ("Nickname", self.nickname.clone()),
+            ("Address", identity.address.clone()),
+            ("Device-Id", identity.device_id.clone()),
+            ("Provisioned-Serial", identity.provisioned_serial.clone()),
+            (
+                "Certificate-Fingerprint",
+                identity.certificate_fingerprint.map(hex::encode),
+            ),
+            ("Claimed-At", Some(self.claimed_at.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|v| (name, v)))
+        .collect()
+    }
+
+    fn checksum(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (name, value) in self.headers() {
+            hasher.update(format!("{}: {}\n", name, value));
+        }
+        hasher.update(self.user_secret);
+        hex::encode(&hasher.finalize()[..4])
+    }
+}
+
+// hex, base64 or an armored block, whichever text is, only the armored block
+// says which headset the key belongs to
+pub fn import_key(text: &str) -> Result<KeyEntry, HzospalError> {
+    let text = text.trim();
+
+    if text.starts_with(BEGIN) {
+        return import_armored(text);
+    }
+
+    let compact: String = text.split_whitespace().collect();
+    let user_secret = if compact.len() == 64 && compact.chars().all(|c| c.is_ascii_hexdigit()) {
+        hex::decode(&compact).ok()
+    } else {
+        BASE64.decode(&compact).ok()
+    }
+    .and_then(|key| <[u8; 32]>::try_from(key).ok())
+    .ok_or_else(|| {
+        HzospalError::Keystore("Not a 32 byte hex or base64 key, nor an armored block".into())
+    })?;
+
+    Ok(KeyEntry {
+        nickname: None,
+        identity: HeadsetIdentity::default(),
+        user_secret,
+        pending_secret: None,
+        certificate: None,
+        claimed_at: now(),
+    })
+}
+
+fn import_armored(text: &str) -> Result<KeyEntry, HzospalError> {
+    let bad = |msg: &str| HzospalError::Keystore(format!("Bad armored key: {}", msg));
+
+    let mut lines = text.lines().map(str::trim).skip(1);
+    let mut entry = KeyEntry {
+        nickname: None,
+        identity: HeadsetIdentity::default(),
+        user_secret: [0; 32],
+        pending_secret: None,
+        certificate: None,
+        claimed_at: 0,
+    };
+    let mut checksum = None;
+
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| bad(&format!("expected a header, got {:?}", line)))?;
+        let value = value.trim().to_string();
+
+        match name {
+            "Nickname" => entry.nickname = Some(value),
+            "Address" => entry.identity.address = Some(value),
+            "Device-Id" => entry.identity.device_id = Some(value),
+            "Provisioned-Serial" => entry.identity.provisioned_serial = Some(value),
+            "Certificate-Fingerprint" => {
+                entry.identity.certificate_fingerprint = Some(
+                    <[u8; 32]>::from_hex(&value)
+                        .map_err(|_| bad("certificate fingerprint isn't 32 hex bytes"))?,
+                )
+            }
+            "Claimed-At" => {
+                entry.claimed_at = value
+                    .parse()
+                    .map_err(|_| bad("Claimed-At isn't a number"))?
+            }
+            "Checksum" => checksum = Some(value),
+            _ => return Err(bad(&format!("unknown header {:?}", name))),
+        }
+    }
+
+    let body: String = lines.take_while(|line| *line != END).collect();
+    entry.user_secret = BASE64
+        .decode(body)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .ok_or_else(|| bad("the key isn't 32 base64 encoded bytes"))?;
+
+    let checksum = checksum.ok_or_else(|| bad("no checksum"))?;
+    if !checksum.eq_ignore_ascii_case(&entry.checksum()) {
+        return Err(bad("checksum mismatch, it was mistyped or altered"));
+    }
+
+    Ok(entry)
+}
@@ -0,0 +1,476 @@
+use crate::QuestDevice;
+use crate::error::HzospalError;
+use crate::protocol::certificate::fingerprint;
+use crate::status::HmdStatusResponse;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+mod backup;
+
+pub use backup::{KeyFormat, import_key};
+
+// an encrypted store starts with MAGIC, the format version, the KDF and its
+// parameters, the salt and the nonce, all of it authenticated along with the
+// ciphertext that follows
+const MAGIC: &[u8; 4] = b"HZKS";
+const VERSION: u8 = 1;
+const KDF_ARGON2ID: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 3 * 4 + SALT_LEN + NONCE_LEN;
+
+// argon2id with 64 MiB, slow enough to make guessing expensive on a laptop
+const MEMORY_KIB: u32 = 64 * 1024;
+const ITERATIONS: u32 = 3;
+const PARALLELISM: u32 = 1;
+
+// what tells one headset apart from another, any of it can be missing and
+// the address isn't stable on every platform
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HeadsetIdentity {
+    pub address: Option<String>,
+    pub device_id: Option<String>,
+    pub provisioned_serial: Option<String>,
+    #[serde(default, with = "hex_option")]
+    pub certificate_fingerprint: Option<[u8; 32]>,
+}
+
+impl HeadsetIdentity {
+    fn is_empty(&self) -> bool {
+        *self == HeadsetIdentity::default()
+    }
+
+    // fills in whatever other knows and we don't, other wins on conflicts
+    fn update(&mut self, other: &HeadsetIdentity) {
+        if other.address.is_some() {
+            self.address = other.address.clone();
+        }
+        if other.device_id.is_some() {
+            self.device_id = other.device_id.clone();
+        }
+        if other.provisioned_serial.is_some() {
+            self.provisioned_serial = other.provisioned_serial.clone();
+        }
+        if other.certificate_fingerprint.is_some() {
+            self.certificate_fingerprint = other.certificate_fingerprint;
+        }
+    }
+}
+
+impl QuestDevice {
+    // what we know about the headset without asking it, device_id and
+    // provisioned_serial need a HmdStatusResponse
+    pub fn identity(&self) -> HeadsetIdentity {
+        HeadsetIdentity {
+            address: self.address.clone(),
+            certificate_fingerprint: self
+                .server_certificate
+                .as_deref()
+                .and_then(|c| fingerprint(c).ok()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyEntry {
+    pub nickname: Option<String>,
+    #[serde(flatten)]
+    pub identity: HeadsetIdentity,
+    #[serde(with = "hex::serde")]
+    pub user_secret: [u8; 32],
+    // the key a rekey was switching to, set until it's known whether the
+    // headset took it, so an interrupted rekey can't lock us out
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "hex_option")]
+    pub pending_secret: Option<[u8; 32]>,
+    // the chain pinned on first use
+    #[serde(default, with = "hex_option")]
+    pub certificate: Option<Vec<u8>>,
+    // seconds since the unix epoch
+    pub claimed_at: u64,
+}
+
+// keeps the secret out of logs
+impl fmt::Debug for KeyEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyEntry")
+            .field("nickname", &self.nickname)
+            .field("identity", &self.identity)
+            .field("claimed_at", &self.claimed_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyEntry {
+    // whether query is this headset's nickname, serial, address, device id or
+    // the start of its certificate fingerprint
+    pub fn matches(&self, query: &str) -> bool {
+        let identity = &self.identity;
+        [
+            self.nickname.as_deref(),
+            identity.provisioned_serial.as_deref(),
+            identity.address.as_deref(),
+            identity.device_id.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|value| value.eq_ignore_ascii_case(query))
+            || (query.len() >= 8
+                && identity
+                    .certificate_fingerprint
+                    .is_some_and(|f| hex::encode(f).starts_with(&query.to_ascii_lowercase())))
+    }
+
+    pub fn name(&self) -> String {
+        let identity = &self.identity;
+        self.nickname
+            .clone()
+            .or_else(|| identity.provisioned_serial.clone())
+            .or_else(|| identity.address.clone())
+            .or_else(|| identity.device_id.clone())
+            .or_else(|| {
+                identity
+                    .certificate_fingerprint
+                    .map(|f| hex::encode(&f[..8]))
+            })
+            .unwrap_or_else(|| "Unknown".to_string())
+    }
+}
+
+// the user secret of every headset we claimed, see connect_to_quest
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyStore {
+    pub headsets: Vec<KeyEntry>,
+}
+
+impl KeyStore {
+    // a missing file is an empty store
+    pub fn load(path: &Path) -> Result<Self, HzospalError> {
+        let Some(data) = read(path)? else {
+            return Ok(Self::default());
+        };
+
+        if data.starts_with(MAGIC) {
+            return Err(keystore_error(path, "encrypted, a passphrase is needed"));
+        }
+
+        serde_json::from_slice(&data).map_err(|e| keystore_error(path, e))
+    }
+
+    // plaintext, anyone who can read path gets every key, see save_encrypted
+    pub fn save(&self, path: &Path) -> Result<(), HzospalError> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| keystore_error(path, e))?;
+        write_atomic(path, &data)
+    }
+
+    pub fn load_encrypted(path: &Path, passphrase: &str) -> Result<Self, HzospalError> {
+        let Some(data) = read(path)? else {
+            return Ok(Self::default());
+        };
+
+        let plaintext = decrypt(&data, passphrase).map_err(|e| keystore_error(path, e))?;
+        serde_json::from_slice(&plaintext).map_err(|e| keystore_error(path, e))
+    }
+
+    pub fn save_encrypted(&self, path: &Path, passphrase: &str) -> Result<(), HzospalError> {
+        let plaintext =
+            Zeroizing::new(serde_json::to_vec(self).map_err(|e| keystore_error(path, e))?);
+        let data = encrypt(&plaintext, passphrase).map_err(|e| keystore_error(path, e))?;
+        write_atomic(path, &data)
+    }
+
+    // strongest evidence first, a certificate outlives an address
+    pub fn find(&self, identity: &HeadsetIdentity) -> Option<&KeyEntry> {
+        self.position(identity).map(|i| &self.headsets[i])
+    }
+
+    // find, but an entry found by anything other than the certificate must
+    // not belong to a certificate other than the one presented, it's another
+    // headset or one that was reset
+    pub fn find_checked(
+        &self,
+        identity: &HeadsetIdentity,
+        accept_changed_certificate: bool,
+    ) -> Result<Option<&KeyEntry>, HzospalError> {
+        let Some(entry) = self.find(identity) else {
+            return Ok(None);
+        };
+
+        if let (Some(stored), Some(presented)) = (
+            entry.identity.certificate_fingerprint,
+            identity.certificate_fingerprint,
+        ) && stored != presented
+            && !accept_changed_certificate
+        {
+            return Err(HzospalError::Certificate(format!(
+                "Key for {} belongs to certificate {} but the headset presented {}",
+                entry.name(),
+                hex::encode(stored),
+                hex::encode(presented)
+            )));
+        }
+
+        Ok(Some(entry))
+    }
+
+    fn position(&self, identity: &HeadsetIdentity) -> Option<usize> {
+        let find = |matches: &dyn Fn(&HeadsetIdentity) -> bool| {
+            self.headsets.iter().position(|e| matches(&e.identity))
+        };
+
+        let HeadsetIdentity {
+            address,
+            device_id,
+            provisioned_serial,
+            certificate_fingerprint,
+        } = identity;
+
+        certificate_fingerprint
+            .and_then(|f| find(&|e| e.certificate_fingerprint == Some(f)))
+            .or_else(|| {
+                device_id
+                    .as_ref()
+                    .and_then(|id| find(&|e| e.device_id.as_ref() == Some(id)))
+            })
+            .or_else(|| {
+                provisioned_serial
+                    .as_ref()
+                    .and_then(|s| find(&|e| e.provisioned_serial.as_ref() == Some(s)))
+            })
+            .or_else(|| {
+                address
+                    .as_ref()
+                    .and_then(|a| find(&|e| e.address.as_ref() == Some(a)))
+            })
+    }
+
+    // records quest's key under everything we know about it, updating the
+    // entry it already has, status adds device_id and provisioned_serial
+    pub fn remember(
+        &mut self,
+        quest: &QuestDevice,
+        status: Option<&HmdStatusResponse>,
+    ) -> Result<&mut KeyEntry, HzospalError> {
+        let user_secret = quest
+            .device_key
+            .ok_or_else(|| HzospalError::Keystore("Headset has no device key".into()))?;
+
+        let mut identity = quest.identity();
+        if let Some(status) = status {
+            identity.device_id = status.device_id.clone();
+            identity.provisioned_serial = status.provisioned_serial.clone();
+        }
+
+        self.import(KeyEntry {
+            nickname: None,
+            identity,
+            user_secret,
+            pending_secret: None,
+            certificate: quest.server_certificate.clone(),
+            claimed_at: now(),
+        })
+    }
+
+    // adds entry, or merges it into the entry for the same headset, keeping
+    // whatever entry leaves out, except for pending_secret
+    pub fn import(&mut self, entry: KeyEntry) -> Result<&mut KeyEntry, HzospalError> {
+        if entry.identity.is_empty() {
+            return Err(HzospalError::Keystore(
+                "Nothing identifies the headset, refusing to store its key".into(),
+            ));
+        }
+
+        let Some(index) = self.position(&entry.identity) else {
+            self.headsets.push(entry);
+            return Ok(self.headsets.last_mut().unwrap());
+        };
+
+        let existing = &mut self.headsets[index];
+        existing.identity.update(&entry.identity);
+        existing.user_secret = entry.user_secret;
+        existing.pending_secret = entry.pending_secret;
+        if entry.nickname.is_some() {
+            existing.nickname = entry.nickname;
+        }
+        if entry.certificate.is_some() {
+            existing.certificate = entry.certificate;
+        }
+
+        Ok(existing)
+    }
+
+    // see KeyEntry::matches
+    pub fn select(&self, query: &str) -> Vec<&KeyEntry> {
+        self.headsets.iter().filter(|e| e.matches(query)).collect()
+    }
+
+    pub fn remove(&mut self, identity: &HeadsetIdentity) -> Option<KeyEntry> {
+        self.position(identity).map(|i| self.headsets.remove(i))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn keystore_error(path: &Path, e: impl fmt::Display) -> HzospalError {
+    HzospalError::Keystore(format!("{}: {}", path.display(), e))
+}
+
+fn read(path: &Path) -> Result<Option<Vec<u8>>, HzospalError> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(keystore_error(path, e)),
+    }
+}
+
+// written to a temporary file first so a crash can't lose every key
+fn write_atomic(path: &Path, data: &[u8]) -> Result<(), HzospalError> {
+    let temp = path.with_extension("tmp");
+    write_private(&temp, data).map_err(|e| keystore_error(&temp, e))?;
+    std::fs::rename(&temp, path).map_err(|e| keystore_error(path, e))
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Result<Zeroizing<[u8; 32]>, String> {
+    let params = Params::new(memory_kib, iterations, parallelism, Some(32))
+        .map_err(|e| format!("bad KDF parameters: {}", e))?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| format!("key derivation failed: {}", e))?;
+
+    Ok(key)
+}
+
+fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    rand::fill(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut data = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&[VERSION, KDF_ARGON2ID]);
+    for param in [MEMORY_KIB, ITERATIONS, PARALLELISM] {
+        data.extend_from_slice(&param.to_le_bytes());
+    }
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt, MEMORY_KIB, ITERATIONS, PARALLELISM)?;
+    let ciphertext = XChaCha20Poly1305::new(key.as_ref().into())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &data,
+            },
+        )
+        .map_err(|_| "encryption failed".to_string())?;
+
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    if !data.starts_with(MAGIC) {
+        return Err("not an encrypted key store".into());
+    }
+    if data.len() < HEADER_LEN {
+        return Err("truncated header".into());
+    }
+
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+    let version = header[4];
+    if version != VERSION {
+        return Err(format!("unsupported format version {}", version));
+    }
+    let kdf = header[5];
+    if kdf != KDF_ARGON2ID {
+        return Err(format!("unsupported KDF {}", kdf));
+    }
+
+    let param = |i: usize| u32::from_le_bytes(header[6 + 4 * i..10 + 4 * i].try_into().unwrap());
+    let salt = &header[18..18 + SALT_LEN];
+    let nonce = XNonce::from_slice(&header[18 + SALT_LEN..]);
+
+    // whoever wrote the file picks these, don't let them ask for all our memory
+    // or hours of our time
+    if param(0) > 4 * 1024 * 1024 {
+        return Err(format!("KDF wants {} KiB of memory", param(0)));
+    }
+    if param(1) > 64 {
+        return Err(format!("KDF wants {} iterations", param(1)));
+    }
+    if param(2) > 64 {
+        return Err(format!("KDF wants {} lanes", param(2)));
+    }
+
+    let key = derive_key(passphrase, salt, param(0), param(1), param(2))?;
+    XChaCha20Poly1305::new(key.as_ref().into())
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| "wrong passphrase, or the file was tampered with".to_string())
+}
+
+// only readable by the user, the file holds every headset's secret
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, data)
+}
+
+// hex::serde for optional fields
+mod hex_option {
+    use hex::{FromHex, ToHex};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: ToHex>(data: &Option<T>, s: S) -> Result<S::Ok, S::Error> {
+        data.as_ref().map(|d| d.encode_hex::<String>()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: FromHex>(d: D) -> Result<Option<T>, D::Error>
+    where
+        T::Error: std::fmt::Display,
+    {
+        Option::<String>::deserialize(d)?
+            .map(|s| T::from_hex(s).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
@@ -1,10 +1,23 @@
+pub mod access;
+pub mod adapter;
+pub mod capabilities;
+pub mod error;
+pub mod keepalive;
+pub mod keystore;
+pub mod messages;
 pub mod protocol;
+pub mod scan;
+pub mod session;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+pub mod status;
+pub mod transport;
 
 // absolutely disgusting package naming but I'm just following the docs for prost-build - veygax
 pub(crate) mod com {
     pub mod oculus {
         pub mod companion {
-            #[allow(dead_code)]
+            #[allow(dead_code, clippy::enum_variant_names)]
             pub mod server {
                 include!(concat!(env!("OUT_DIR"), "/com.oculus.companion.server.rs"));
             }
@@ -12,130 +25,286 @@ pub(crate) mod com {
     }
 }
 
+use crate::access::{PinPrompt, unlock};
+use crate::adapter::AdapterSelector;
+use crate::capabilities::Capabilities;
+use crate::com::oculus::companion::server::Method;
+use crate::error::{ErrorCode, HzospalError};
+use crate::keepalive::LinkHealth;
+use crate::keystore::{HeadsetIdentity, KeyStore};
+use crate::protocol::certificate::{CertificateVerification, fingerprint};
 use crate::protocol::functions::{authenticate_device, claim_device, say_hello};
-use btleplug::api::{
-    Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, ScanFilter,
-};
-use btleplug::platform::{Manager, Peripheral};
+use crate::protocol::retry::RetryPolicy;
+use crate::scan::QuestSelector;
+use crate::session::{Connector, ReconnectPolicy, Session};
+use crate::transport::QuestTransport;
+use crypto_box::SecretKey;
 use crypto_box::aead::OsRng;
-use crypto_box::{SalsaBox, SecretKey};
-use futures::stream::StreamExt;
 use log::*;
-use std::error::Error;
+use std::collections::HashMap;
 use std::sync::atomic::AtomicI32;
-use uuid::{Uuid, uuid};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 pub struct QuestDevice {
-    pub peripheral: Peripheral,
     pub name: String,
-    pub ccs_characteristic: Characteristic,
-    pub status_characteristic: Characteristic,
+    // Bluetooth address, if we found the headset by scanning
+    pub address: Option<String>,
     pub x25519_keypair: (SecretKey, [u8; 32]),
-    pub crypto_box: Option<SalsaBox>,
     pub sequence_number: AtomicI32,
     pub device_key: Option<[u8; 32]>,
+    // the certificate the headset proved itself with, later connections,
+    // reconnects included, refuse any other
+    pub server_certificate: Option<Vec<u8>>,
+    pub retry_policy: RetryPolicy,
+    pub reconnect_policy: ReconnectPolicy,
+    pub timeouts: Timeouts,
+    pub(crate) certificate_verification: CertificateVerification,
+    accept_changed_certificate: bool,
+    // the pin when all we know of the certificate is its fingerprint
+    pub(crate) pinned_fingerprint: Option<[u8; 32]>,
+    pub(crate) session: RwLock<Arc<Session>>,
+    pub(crate) health: Mutex<LinkHealth>,
+    connector: Option<Connector>,
+    reconnect_lock: tokio::sync::Mutex<()>,
+    mtu_override: Option<usize>,
+    pub(crate) pin_prompt: Option<PinPrompt>,
+    pub(crate) capabilities: Mutex<Option<Capabilities>>,
 }
 
-pub async fn connect_to_quest(
-    device_key: Option<[u8; 32]>,
-) -> Result<Option<QuestDevice>, Box<dyn Error>> {
-    const QUEST_UUID: Uuid = uuid!("0000feb8-0000-1000-8000-00805f9b34fb");
-    const CCS_UUID: Uuid = uuid!("7a442881-509c-47fa-ac02-b06a37d9eb76");
-    const STATUS_UUID: Uuid = uuid!("7a442666-509c-47fa-ac02-b06a37d9eb76");
-
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let central = adapters.first().ok_or("No Bluetooth adapters discovered")?;
-
-    for peripheral in central.peripherals().await? {
-        if peripheral.is_connected().await? {
-            if let Some(properties) = peripheral.properties().await? {
-                if properties.services.contains(&QUEST_UUID) {
-                    peripheral.disconnect().await?;
-                }
-            }
+// the minimum ATT MTU every BLE link has to support
+pub const DEFAULT_MTU: usize = 23;
+
+#[derive(Clone, Debug)]
+pub struct Timeouts {
+    // None keeps scanning until a headset shows up
+    pub scan: Option<Duration>,
+    // how long a reconnect waits for the headset to show up again
+    pub reconnect_scan: Duration,
+    pub connect: Duration,
+    pub service_discovery: Duration,
+    // hello plus claim or authenticate
+    pub handshake: Duration,
+    // default for every RPC without an entry in methods
+    pub rpc: Duration,
+    pub methods: HashMap<Method, Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            scan: None,
+            reconnect_scan: Duration::from_secs(30),
+            connect: Duration::from_secs(30),
+            service_discovery: Duration::from_secs(30),
+            handshake: Duration::from_secs(30),
+            rpc: Duration::from_secs(30),
+            // these wait on the headset doing real work before it answers
+            methods: HashMap::from([
+                (Method::WifiConnect, Duration::from_secs(90)),
+                (Method::ControllerScan, Duration::from_secs(60)),
+                (Method::ControllerPair, Duration::from_secs(120)),
+                (Method::ControllerScanAndPair, Duration::from_secs(120)),
+                (Method::ControllerVerifyConnectable, Duration::from_secs(60)),
+                (
+                    Method::VerifyMultipleControllersConnectable,
+                    Duration::from_secs(60),
+                ),
+            ]),
         }
     }
+}
 
-    let mut events = central.events().await?;
+impl Timeouts {
+    pub fn for_method(&self, method: Method) -> Duration {
+        self.methods.get(&method).copied().unwrap_or(self.rpc)
+    }
+}
 
-    central.start_scan(ScanFilter::default()).await?;
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionOptions {
+    // skip MTU discovery and fragment for this MTU instead, for adapters that
+    // report one they can't actually handle
+    pub mtu: Option<usize>,
+    pub retry_policy: RetryPolicy,
+    pub reconnect_policy: ReconnectPolicy,
+    pub timeouts: Timeouts,
+    pub adapter: AdapterSelector,
+    pub certificate_verification: CertificateVerification,
+    // what QuestDevice.server_certificate held last time, None trusts whatever
+    // the headset presents on first use
+    pub pinned_certificate: Option<Vec<u8>>,
+    // the fingerprint of the certificate to pin when we don't have it, like
+    // for a key imported from an armored block, pinned_certificate wins
+    pub pinned_fingerprint: Option<[u8; 32]>,
+    // connect even if the headset presents a certificate other than the
+    // pinned one, e.g. after it was replaced or factory reset
+    pub accept_changed_certificate: bool,
+    // where to look up the key of a claimed headset once it said hello, and
+    // its pinned certificate, a device_key passed in explicitly wins
+    pub keystore: Option<KeyStore>,
+    // asked for the PIN of a headset that says it needs unlocking, without
+    // one connecting to a locked headset fails
+    pub pin_prompt: Option<PinPrompt>,
+}
 
-    while let Some(event) = events.next().await {
-        let id = match event {
-            CentralEvent::DeviceDiscovered(id) => id,
-            CentralEvent::DeviceUpdated(id) => id,
-            _ => continue,
-        };
+pub(crate) async fn with_timeout<T>(
+    duration: Duration,
+    what: &str,
+    future: impl Future<Output = Result<T, HzospalError>>,
+) -> Result<T, HzospalError> {
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| HzospalError::Timeout(what.to_string()))?
+}
 
-        if let Ok(peripheral) = central.peripheral(&id).await {
-            if let Some(properties) = peripheral.properties().await? {
-                if properties.services.contains(&QUEST_UUID) {
-                    let name = properties
-                        .local_name
-                        .as_deref()
-                        .unwrap_or("Unknown")
-                        .to_string();
+// connects to the first headset found, see scan::connect to pick one
+pub async fn connect_to_quest(
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<Option<QuestDevice>, HzospalError> {
+    scan::connect(QuestSelector::Any, device_key, options).await
+}
 
-                    let rssi = properties.rssi.unwrap_or(0);
+// runs the hello/claim/authenticate handshake over an already connected link,
+// the session can't be re-established if that link drops
+pub async fn connect_with_transport(
+    transport: Box<dyn QuestTransport>,
+    name: String,
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<QuestDevice, HzospalError> {
+    open_quest(transport, None, name, None, device_key, options).await
+}
 
-                    debug!("Found {}, {} RSSI", name, rssi);
+// same as connect_with_transport, but connector is used to open the link and
+// to open it again whenever it drops
+pub async fn connect_with_connector(
+    connector: Connector,
+    name: String,
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<QuestDevice, HzospalError> {
+    let transport = connector().await?;
+    open_quest(transport, Some(connector), name, None, device_key, options).await
+}
 
-                    central.stop_scan().await?;
+pub(crate) async fn open_quest(
+    transport: Box<dyn QuestTransport>,
+    connector: Option<Connector>,
+    name: String,
+    address: Option<String>,
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<QuestDevice, HzospalError> {
+    let x25519_keypair: (SecretKey, [u8; 32]) = generate_x25519_keypair();
+    let session = Session::open(transport, options.mtu).await?;
 
-                    debug!("Connecting to {}...", name);
-                    peripheral.connect().await?;
-                    debug!("Connected.");
+    let keystore = options.keystore.unwrap_or_default();
 
-                    peripheral.discover_services().await?;
-                    let characteristics = peripheral.characteristics();
+    // the stored certificate is the pin unless the caller brought their own,
+    // or only its fingerprint if that's all the store has
+    let stored = keystore.find(&HeadsetIdentity {
+        address: address.clone(),
+        ..Default::default()
+    });
+    let pinned_certificate = options
+        .pinned_certificate
+        .or_else(|| stored.and_then(|e| e.certificate.clone()));
+    let pinned_fingerprint = options
+        .pinned_fingerprint
+        .or_else(|| stored.and_then(|e| e.identity.certificate_fingerprint));
 
-                    let ccs_characteristic = characteristics
-                        .iter()
-                        .find(|c| c.uuid == CCS_UUID)
-                        .cloned()
-                        .ok_or("Failed to find CCS characteristic")?;
+    let mut quest = QuestDevice {
+        name,
+        address,
+        x25519_keypair,
+        sequence_number: AtomicI32::new(0),
+        device_key,
+        server_certificate: pinned_certificate,
+        retry_policy: options.retry_policy,
+        reconnect_policy: options.reconnect_policy,
+        timeouts: options.timeouts,
+        certificate_verification: options.certificate_verification,
+        accept_changed_certificate: options.accept_changed_certificate,
+        pinned_fingerprint,
+        session: RwLock::new(session.clone()),
+        health: Mutex::new(LinkHealth::default()),
+        connector,
+        reconnect_lock: tokio::sync::Mutex::new(()),
+        mtu_override: options.mtu,
+        pin_prompt: options.pin_prompt,
+        capabilities: Mutex::new(None),
+    };
 
-                    let status_characteristic = characteristics
-                        .iter()
-                        .find(|c| c.uuid == STATUS_UUID)
-                        .cloned()
-                        .ok_or("Failed to find status characteristic")?;
+    let handshake_timeout = quest.timeouts.handshake;
+    let hello = with_timeout(handshake_timeout, "handshake", say_hello(&quest, &session)).await?;
 
-                    let x25519_keypair: (SecretKey, [u8; 32]) = generate_x25519_keypair();
+    // a locked headset refuses to authenticate or be claimed, and the PIN
+    // may take longer to come than the handshake timeout allows
+    if hello.info.device_needs_to_be_unlocked {
+        unlock(&quest, &session).await?;
+    }
+    if hello.info.is_device_managed {
+        debug!("Headset is managed, restricted methods will be refused");
+    }
 
-                    let mut quest = QuestDevice {
-                        peripheral,
-                        name,
-                        ccs_characteristic,
-                        status_characteristic,
-                        x25519_keypair,
-                        crypto_box: None,
-                        sequence_number: AtomicI32::new(0),
-                        device_key,
-                    };
+    let (certificate, device_key) = with_timeout(handshake_timeout, "handshake", async {
+        // the Quest only returns a challenge if it's claimed
+        let device_key = match hello.challenge {
+            Some(c) => {
+                // now that we know who we're talking to, pick its key
+                let identity = HeadsetIdentity {
+                    certificate_fingerprint: hello
+                        .certificate
+                        .as_deref()
+                        .map(fingerprint)
+                        .transpose()?,
+                    ..quest.identity()
+                };
+                let (key, pending) = match device_key {
+                    Some(key) => (key, None),
+                    None => keystore
+                        .find_checked(&identity, quest.accept_changed_certificate)?
+                        .map(|e| (e.user_secret, e.pending_secret))
+                        .ok_or_else(|| {
+                            HzospalError::Crypto(
+                                "Headset is claimed but we have no key for it".into(),
+                            )
+                        })?,
+                };
 
-                    let challenge = say_hello(&mut quest).await?;
+                match authenticate_device(&quest, &session, &key, c.clone()).await {
+                    Ok(()) => key,
+                    // a rekey was interrupted after the headset took the new key
+                    Err(e) if e.error_code() == Some(ErrorCode::AuthenticationFailure) => {
+                        let Some(pending) = pending else {
+                            return Err(e);
+                        };
+                        debug!("Stored key refused, trying the one a rekey was switching to");
+                        authenticate_device(&quest, &session, &pending, c).await?;
+                        pending
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            None => claim_device(&quest, &session, device_key).await?,
+        };
 
-                    // the Quest only returns a challenge if it's claimed
-                    match challenge {
-                        Some(c) => authenticate_device(&quest, c).await?,
-                        None => claim_device(&mut quest, device_key).await?,
-                    };
+        Ok((hello.certificate, device_key))
+    })
+    .await?;
 
-                    debug!("Authenticated device!");
+    quest.server_certificate = certificate;
+    quest.device_key = Some(device_key);
 
-                    return Ok(Some(quest));
-                }
-            }
-        }
-    }
+    debug!("Authenticated device!");
 
-    Ok(None)
+    Ok(quest)
 }
 
 fn generate_x25519_keypair() -> (SecretKey, [u8; 32]) {
     let secret_key = SecretKey::generate(&mut OsRng);
-    let public_key = secret_key.public_key().as_bytes().clone();
+    let public_key = *secret_key.public_key().as_bytes();
     (secret_key, public_key)
 }
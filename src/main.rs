@@ -1,10 +1,166 @@
+use btleplug::api::BDAddr;
+use clap::{Args, Parser, Subcommand};
 use hzospal::{
-    QuestDevice, connect_to_quest,
-    protocol::functions::{connect_to_wifi, get_hmd_status, set_dev_mode, set_ota_mode, skip_nux},
+    ConnectionOptions, QuestDevice, Timeouts,
+    access::{PinAttempt, PinPrompt},
+    adapter::{AdapterSelector, list_adapters},
+    error::{DeviceError, HzospalError},
+    keystore::{HeadsetIdentity, KeyFormat, KeyStore, import_key},
+    protocol::{
+        certificate::CertificateVerification,
+        functions::{Method, get_hmd_status, rekey},
+    },
+    scan::{QuestSelector, connect, scan_quests_on},
 };
 //use ratatui::{DefaultTerminal, Frame};
 use directories::ProjectDirs;
 use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+// checked before passphrase_command and prompting
+const PASSPHRASE_ENV: &str = "HZOSPAL_PASSPHRASE";
+
+type Passphrase = Zeroizing<String>;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Index, address or name (wildcards allowed) of the Bluetooth adapter, or
+    /// "all" to use every adapter
+    #[arg(short, long, global = true, default_value = "0")]
+    adapter: AdapterSelector,
+
+    /// Certificate (PEM or DER) the headset's certificate chain has to lead to,
+    /// can be given more than once
+    #[arg(long, global = true, value_name = "FILE")]
+    trust_anchor: Vec<PathBuf>,
+
+    /// Don't verify the headset's certificate, anyone nearby can impersonate it
+    #[arg(long, global = true, conflicts_with = "trust_anchor")]
+    insecure: bool,
+
+    /// Connect even if the headset's certificate differs from the one saved on
+    /// first use, and save the new one
+    #[arg(long, global = true)]
+    accept_changed_certificate: bool,
+
+    /// Command whose output is the key store passphrase, for automation or a
+    /// password manager, HZOSPAL_PASSPHRASE takes precedence
+    #[arg(long, global = true, value_name = "COMMAND")]
+    passphrase_command: Option<String>,
+
+    /// How long to look for the headset before giving up, in seconds
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 30)]
+    scan_timeout: u64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List Bluetooth adapters
+    Adapters,
+    /// List nearby headsets
+    Scan {
+        /// How long to scan for
+        #[arg(short, long, default_value_t = 5)]
+        seconds: u64,
+    },
+    /// Manage the keys of claimed headsets
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+    /// Connect and print the headset status (default)
+    Status {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Replace the headset's key with a new one, the old key stops working
+    Rekey {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Send any method and print its response as JSON
+    Call {
+        /// Method name as in Protocol.proto, e.g. HMD_VERSION or wifi_scan
+        method: String,
+        /// Request message as JSON, an empty one is sent if left out
+        #[arg(long, value_name = "JSON")]
+        json: Option<String>,
+        #[command(flatten)]
+        target: Target,
+    },
+}
+
+impl Command {
+    // None for the commands that don't connect
+    fn target(&self) -> Option<&Target> {
+        match self {
+            Command::Status { target }
+            | Command::Rekey { target }
+            | Command::Call { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+}
+
+// which headset to connect to, the first one advertising if neither is given
+#[derive(Args, Default)]
+struct Target {
+    /// Bluetooth address of the headset to connect to
+    #[arg(long, conflicts_with = "name")]
+    address: Option<BDAddr>,
+    /// Name (wildcards allowed) of the headset to connect to
+    #[arg(long)]
+    name: Option<String>,
+}
+
+impl Target {
+    fn selector(&self) -> QuestSelector {
+        match (self.address, &self.name) {
+            (Some(address), _) => QuestSelector::Address(address),
+            (None, Some(name)) => QuestSelector::Name(name.clone()),
+            (None, None) => QuestSelector::Any,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    /// List headsets we hold a key for
+    List,
+    /// Print a headset's key, to back it up or move it to another machine
+    Export {
+        /// Nickname, serial, address, device id or certificate fingerprint of
+        /// the headset, can be left out if only one is stored
+        headset: Option<String>,
+        /// hex, base64, armored or qr
+        #[arg(short, long, default_value = "armored")]
+        format: KeyFormat,
+    },
+    /// Add a key printed by "key export", read from FILE or standard input
+    Import {
+        file: Option<PathBuf>,
+        /// Name to list the headset under
+        #[arg(long)]
+        nickname: Option<String>,
+        /// Bluetooth address of the headset, hex and base64 keys need this,
+        /// --serial or --device-id to know which headset they belong to
+        #[arg(long)]
+        address: Option<String>,
+        /// Provisioned serial of the headset
+        #[arg(long)]
+        serial: Option<String>,
+        /// Device id of the headset
+        #[arg(long)]
+        device_id: Option<String>,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -18,6 +174,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     env_logger::init();
 
+    let cli = Cli::parse();
+
     let proj_dirs = ProjectDirs::from("com", "veygax", "hzospal")
         .ok_or("Could not determine config directory")?;
     let config_dir = proj_dirs.config_dir();
@@ -26,31 +184,283 @@ async fn main() -> Result<(), Box<dyn Error>> {
         std::fs::create_dir_all(config_dir)?;
     }
 
+    let keystore_path = config_dir.join("keys.enc");
+    // plaintext, from before the key store was encrypted
+    let plain_keystore_path = config_dir.join("keys.json");
+    let passphrase_command = cli.passphrase_command.as_deref();
+
+    let command = match cli.command.unwrap_or(Command::Status {
+        target: Target::default(),
+    }) {
+        Command::Adapters => {
+            for adapter in list_adapters().await? {
+                match adapter.address {
+                    Some(address) => println!("{}: {} {}", adapter.index, adapter.info, address),
+                    None => println!("{}: {}", adapter.index, adapter.info),
+                }
+            }
+            return Ok(());
+        }
+        Command::Scan { seconds } => {
+            let quests = scan_quests_on(&cli.adapter, Duration::from_secs(seconds)).await?;
+            for quest in quests {
+                println!(
+                    "{} {} {} dBm (adapter {})",
+                    quest.address,
+                    quest.name(),
+                    quest.rssi.unwrap_or(0),
+                    quest.adapter
+                );
+            }
+            return Ok(());
+        }
+        Command::Key { command } => {
+            let (mut keystore, mut passphrase) =
+                open_keystore(&keystore_path, &plain_keystore_path, passphrase_command)?;
+
+            match command {
+                KeyCommand::List => {
+                    for entry in &keystore.headsets {
+                        println!(
+                            "{} address {} serial {} claimed at {}",
+                            entry.name(),
+                            entry.identity.address.as_deref().unwrap_or("unknown"),
+                            entry
+                                .identity
+                                .provisioned_serial
+                                .as_deref()
+                                .unwrap_or("unknown"),
+                            entry.claimed_at
+                        );
+                    }
+                }
+                KeyCommand::Export { headset, format } => {
+                    let entries = match &headset {
+                        Some(query) => keystore.select(query),
+                        None => keystore.headsets.iter().collect(),
+                    };
+                    let entry = match entries.as_slice() {
+                        [entry] => *entry,
+                        [] => return Err("No stored headset matches".into()),
+                        _ => {
+                            return Err(
+                                "More than one stored headset matches, say which one".into()
+                            );
+                        }
+                    };
+
+                    eprintln!(
+                        "Anyone holding this key can take over {}, keep it safe",
+                        entry.name()
+                    );
+                    println!("{}", entry.export(format)?.trim_end());
+                }
+                KeyCommand::Import {
+                    file,
+                    nickname,
+                    address,
+                    serial,
+                    device_id,
+                } => {
+                    let text = match file {
+                        Some(path) => Zeroizing::new(std::fs::read_to_string(path)?),
+                        None => {
+                            let mut text = Zeroizing::new(String::new());
+                            std::io::stdin().read_to_string(&mut text)?;
+                            text
+                        }
+                    };
+
+                    let mut entry = import_key(&text)?;
+                    if entry.identity == HeadsetIdentity::default()
+                        && address.is_none()
+                        && serial.is_none()
+                        && device_id.is_none()
+                    {
+                        return Err("This key doesn't say which headset it belongs to, pass --address, --serial or --device-id".into());
+                    }
+
+                    let identity = &mut entry.identity;
+                    identity.address = address.or(identity.address.take());
+                    identity.provisioned_serial = serial.or(identity.provisioned_serial.take());
+                    identity.device_id = device_id.or(identity.device_id.take());
+                    entry.nickname = nickname.or(entry.nickname);
+
+                    let name = keystore.import(entry)?.name();
+                    save_keystore(
+                        &keystore_path,
+                        &plain_keystore_path,
+                        &keystore,
+                        &mut passphrase,
+                        passphrase_command,
+                    )?;
+                    println!("Imported the key for {}", name);
+                }
+            }
+            return Ok(());
+        }
+        command => command,
+    };
+
+    // before connecting, a typo shouldn't cost a connection
+    let call = match &command {
+        Command::Call { method, json, .. } => Some(parse_call(method, json.as_deref())?),
+        _ => None,
+    };
+
+    let (mut keystore, mut passphrase) =
+        open_keystore(&keystore_path, &plain_keystore_path, passphrase_command)?;
+
+    // without keys.enc the key of a headset we're about to claim needs a new
+    // passphrase, a typo after the claim would leave it with nowhere to go
+    if passphrase.is_none() {
+        passphrase = Some(read_passphrase(passphrase_command, true)?);
+    }
+
+    // from before keys.json, a single key and certificate for whichever
+    // headset was claimed first, only used until keys.json has an entry
     let key_path = config_dir.join("device_key.bin");
+    let certificate_path = config_dir.join("server_certificate.der");
 
-    let device_key = if key_path.exists() {
+    let (device_key, pinned_certificate) = if keystore.headsets.is_empty() && key_path.exists() {
         let key_vec = std::fs::read(&key_path)?;
-        Some(
-            key_vec
-                .try_into()
-                .map_err(|_| "device_key.bin > 32 bytes")?,
-        )
+        let key: [u8; 32] = key_vec
+            .try_into()
+            .map_err(|_| "device_key.bin > 32 bytes")?;
+        let certificate = if certificate_path.exists() {
+            Some(std::fs::read(&certificate_path)?)
+        } else {
+            None
+        };
+        (Some(key), certificate)
+    } else {
+        (None, None)
+    };
+
+    let certificate_verification = if cli.insecure {
+        CertificateVerification::Insecure
     } else {
-        None
+        let mut anchors = Vec::new();
+        for path in &cli.trust_anchor {
+            anchors.extend(CertificateVerification::load_anchors(&std::fs::read(
+                path,
+            )?)?);
+        }
+        CertificateVerification::TrustAnchors(anchors)
     };
 
-    let quest: QuestDevice = connect_to_quest(device_key)
-        .await?
-        .ok_or("Quest not found")?;
+    let selector = command.target().map(Target::selector).unwrap_or_default();
+    let mut quest: QuestDevice = connect(
+        selector,
+        device_key,
+        ConnectionOptions {
+            adapter: cli.adapter,
+            certificate_verification,
+            pinned_certificate,
+            accept_changed_certificate: cli.accept_changed_certificate,
+            keystore: Some(keystore.clone()),
+            pin_prompt: Some(pin_prompt()),
+            timeouts: Timeouts {
+                scan: Some(Duration::from_secs(cli.scan_timeout)),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .await?
+    .ok_or("Quest not found")?;
+
+    let handshake = quest.handshake();
+    if handshake.is_device_managed {
+        eprintln!(
+            "{} is managed by an organisation, some settings can't be changed",
+            quest.name
+        );
+    }
+
+    // saved before anything else is asked, a headset claimed just now is
+    // lost for good if its key only lives in memory when a later call fails
+    let before = keystore.clone();
+    keystore.remember(&quest, None)?;
+
+    // anything still lying around in plaintext moves into keys.enc
+    let legacy = device_key
+        .is_some()
+        .then_some([&key_path, &certificate_path]);
+
+    if keystore != before || legacy.is_some() || plain_keystore_path.exists() {
+        save_keystore(
+            &keystore_path,
+            &plain_keystore_path,
+            &keystore,
+            &mut passphrase,
+            passphrase_command,
+        )?;
+
+        for path in legacy.into_iter().flatten() {
+            remove_plaintext(path)?;
+        }
+    }
+
+    let status = get_hmd_status(&quest).await?;
+
+    // the device id and serial only come with the status
+    let before = keystore.clone();
+    keystore.remember(&quest, Some(&status))?;
+    if keystore != before {
+        save_keystore(
+            &keystore_path,
+            &plain_keystore_path,
+            &keystore,
+            &mut passphrase,
+            passphrase_command,
+        )?;
+    }
+
+    if let Some((method, request)) = call {
+        match quest.call_json(method, request).await {
+            Ok(response) => println!("{}", serde_json::to_string_pretty(&response)?),
+            Err(HzospalError::Device(e)) => {
+                println!("{}", serde_json::to_string_pretty(&error_details_json(&e))?);
+                return Err(HzospalError::Device(e).into());
+            }
+            Err(e) => return Err(e.into()),
+        }
+        return Ok(());
+    }
 
-    if !key_path.exists() {
-        if let Some(key) = quest.device_key {
-            std::fs::write(&key_path, key)?;
-            println!("Saved new device key to {:?}", key_path);
+    if let Command::Rekey { .. } = command {
+        if handshake.is_device_managed {
+            return Err("Can't rekey a managed headset".into());
         }
+
+        // both keys are on disk before the headset sees the new one, the
+        // next connection tries both if we die halfway through
+        let new_key: [u8; 32] = rand::random();
+        keystore.remember(&quest, Some(&status))?.pending_secret = Some(new_key);
+        save_keystore(
+            &keystore_path,
+            &plain_keystore_path,
+            &keystore,
+            &mut passphrase,
+            passphrase_command,
+        )?;
+
+        rekey(&mut quest, new_key).await?;
+
+        keystore.remember(&quest, Some(&status))?;
+        save_keystore(
+            &keystore_path,
+            &plain_keystore_path,
+            &keystore,
+            &mut passphrase,
+            passphrase_command,
+        )?;
+        println!("Installed a new device key, the old one no longer works");
+        return Ok(());
     }
 
-    get_hmd_status(&quest).await?;
+    println!("{:#?}", status);
     // skip_nux(&quest).await?;
 
     // set_dev_mode(&quest, true).await?;
@@ -59,6 +469,145 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// the method and request of a call, refusing the methods that would undo
+// what connecting did or leave the key store behind
+fn parse_call(
+    method: &str,
+    json: Option<&str>,
+) -> Result<(Method, Option<serde_json::Value>), Box<dyn Error>> {
+    let name = method.to_ascii_uppercase().replace('-', "_");
+    let method = Method::from_str_name(&name)
+        .ok_or_else(|| format!("No method called {:?} in Protocol.proto", method))?;
+    match method {
+        // the new secret would never reach keys.enc, locking us out
+        Method::OculusSetUserSecret => {
+            return Err("Use rekey to change the headset's key, call can't store it".into());
+        }
+        // the handshake already happened
+        Method::Hello | Method::Authenticate => {
+            return Err(format!("{} is part of the handshake, call can't send it", name).into());
+        }
+        _ => {}
+    }
+
+    let request = json
+        .map(serde_json::from_str::<serde_json::Value>)
+        .transpose()
+        .map_err(|e| format!("--json isn't valid JSON: {}", e))?;
+    Ok((method, request))
+}
+
+// keys.enc if there is one, otherwise whatever keys.json holds, along with
+// the passphrase if we had to ask for it
+fn open_keystore(
+    path: &Path,
+    plain_path: &Path,
+    passphrase_command: Option<&str>,
+) -> Result<(KeyStore, Option<Passphrase>), Box<dyn Error>> {
+    if !path.exists() {
+        return Ok((KeyStore::load(plain_path)?, None));
+    }
+
+    let passphrase = read_passphrase(passphrase_command, false)?;
+    let keystore = KeyStore::load_encrypted(path, &passphrase)?;
+    Ok((keystore, Some(passphrase)))
+}
+
+// encrypted with passphrase, asking for a new one if there was no store yet,
+// keys.json goes away once its keys are in keys.enc
+fn save_keystore(
+    path: &Path,
+    plain_path: &Path,
+    keystore: &KeyStore,
+    passphrase: &mut Option<Passphrase>,
+    passphrase_command: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let passphrase = match passphrase {
+        Some(passphrase) => passphrase,
+        None => passphrase.insert(read_passphrase(passphrase_command, true)?),
+    };
+    keystore.save_encrypted(path, passphrase)?;
+    eprintln!("Saved device keys to {:?}", path);
+
+    remove_plaintext(plain_path)
+}
+
+fn remove_plaintext(path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+        eprintln!("Removed plaintext {:?}", path);
+    }
+    Ok(())
+}
+
+// ErrorDetails as the headset sent them, proto names for the enums
+fn error_details_json(e: &DeviceError) -> serde_json::Value {
+    serde_json::json!({
+        "response_code": e.response_code.as_str_name(),
+        "code": e.code.as_str_name(),
+        "debug_details": e.debug_details,
+        "localized_user_facing_description": e.localized_user_facing_description,
+        "ble_pairing_key": e.ble_pairing_key,
+    })
+}
+
+// asks on the terminal, an empty PIN gives up
+fn pin_prompt() -> PinPrompt {
+    PinPrompt::new(|attempt: PinAttempt| {
+        Box::pin(async move {
+            if attempt.attempt > 1 {
+                eprintln!("Wrong PIN");
+            }
+            let prompt = format!("PIN for {}: ", attempt.headset);
+            tokio::task::spawn_blocking(move || rpassword::prompt_password(prompt).ok())
+                .await
+                .ok()
+                .flatten()
+                .filter(|pin| !pin.is_empty())
+        })
+    })
+}
+
+fn read_passphrase(command: Option<&str>, new: bool) -> Result<Passphrase, Box<dyn Error>> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    if let Some(command) = command {
+        #[cfg(windows)]
+        let output = std::process::Command::new("cmd")
+            .args(["/C", command])
+            .output()?;
+        #[cfg(not(windows))]
+        let output = std::process::Command::new("sh")
+            .args(["-c", command])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(format!("Passphrase command failed with {}", output.status).into());
+        }
+
+        let passphrase = Zeroizing::new(String::from_utf8(output.stdout)?);
+        return Ok(Zeroizing::new(
+            passphrase.trim_end_matches(['\r', '\n']).to_string(),
+        ));
+    }
+
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Key store passphrase: ")?);
+
+    if new {
+        if passphrase.is_empty() {
+            return Err("The key store passphrase can't be empty".into());
+        }
+        let repeated = Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ")?);
+        if passphrase != repeated {
+            return Err("Passphrases don't match".into());
+        }
+    }
+
+    Ok(passphrase)
+}
+
 // remove ratatui until after protocol - veygax
 
 // fn app(
@@ -119,3 +668,57 @@ async fn main() -> Result<(), Box<dyn Error>> {
 //
 //     frame.render_widget(ratatui::widgets::Paragraph::new(text), frame.area());
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector(args: &[&str]) -> QuestSelector {
+        let cli = Cli::try_parse_from(args).unwrap();
+        cli.command.unwrap().target().unwrap().selector()
+    }
+
+    #[test]
+    fn picks_the_headset_to_connect_to() {
+        assert!(matches!(
+            selector(&["hzospal", "status"]),
+            QuestSelector::Any
+        ));
+        assert!(matches!(
+            selector(&["hzospal", "rekey", "--address", "01:02:03:04:05:06"]),
+            QuestSelector::Address(a) if a.to_string() == "01:02:03:04:05:06"
+        ));
+        assert!(matches!(
+            selector(&["hzospal", "call", "hmd_status", "--name", "Quest*"]),
+            QuestSelector::Name(n) if n == "Quest*"
+        ));
+    }
+
+    #[test]
+    fn address_and_name_conflict() {
+        let args = ["hzospal", "status", "--address", "01:02:03:04:05:06"];
+        assert!(Cli::try_parse_from(args.iter().chain(&["--name", "Quest"])).is_err());
+    }
+
+    #[test]
+    fn gives_up_scanning_by_default() {
+        let cli = Cli::try_parse_from(["hzospal", "status"]).unwrap();
+        assert_eq!(cli.scan_timeout, 30);
+
+        let cli = Cli::try_parse_from(["hzospal", "rekey", "--scan-timeout", "5"]).unwrap();
+        assert_eq!(cli.scan_timeout, 5);
+    }
+
+    #[test]
+    fn call_refuses_what_would_lose_the_key() {
+        assert!(parse_call("oculus_set_user_secret", Some("{}")).is_err());
+        assert!(parse_call("HELLO", None).is_err());
+        assert!(parse_call("authenticate", None).is_err());
+        assert!(parse_call("NOPE", None).is_err());
+        assert!(parse_call("hmd_status", Some("{")).is_err());
+
+        let (method, request) = parse_call("hmd-status", None).unwrap();
+        assert_eq!(method, Method::HmdStatus);
+        assert!(request.is_none());
+    }
+}
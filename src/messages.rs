@@ -0,0 +1,76 @@
+// every message and enum in Protocol.proto, with the serde feature they
+// (de)serialize with enums as their proto names, e.g. "HMD_STATUS"
+pub use crate::com::oculus::companion::server::*;
+
+// for enum fields, which prost keeps as i32, build.rs points them here
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum EnumValue<E> {
+    Known(E),
+    // a value this Protocol.proto doesn't know, kept as the number
+    Unknown(i32),
+}
+
+#[cfg(feature = "serde")]
+impl<E: TryFrom<i32> + Into<i32>> EnumValue<E> {
+    fn new(value: i32) -> Self {
+        E::try_from(value)
+            .map(EnumValue::Known)
+            .unwrap_or(EnumValue::Unknown(value))
+    }
+
+    fn value(self) -> i32 {
+        match self {
+            EnumValue::Known(e) => e.into(),
+            EnumValue::Unknown(value) => value,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) mod optional_enum {
+    use super::EnumValue;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<E, S>(value: &Option<i32>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        E: TryFrom<i32> + Into<i32> + Serialize,
+        S: Serializer,
+    {
+        value.map(EnumValue::<E>::new).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, E, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+    where
+        E: TryFrom<i32> + Into<i32> + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<EnumValue<E>>::deserialize(deserializer)?.map(EnumValue::value))
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) mod repeated_enum {
+    use super::EnumValue;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<E, S>(values: &[i32], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        E: TryFrom<i32> + Into<i32> + Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(values.iter().map(|v| EnumValue::<E>::new(*v)))
+    }
+
+    pub(crate) fn deserialize<'de, E, D>(deserializer: D) -> Result<Vec<i32>, D::Error>
+    where
+        E: TryFrom<i32> + Into<i32> + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<EnumValue<E>>::deserialize(deserializer)?
+            .into_iter()
+            .map(EnumValue::value)
+            .collect())
+    }
+}
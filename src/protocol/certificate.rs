@@ -0,0 +1,246 @@
+use crate::error::HzospalError;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{DerSignature, Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+use x509_cert::Certificate;
+use x509_cert::der::{self, Decode, Encode, Reader, SliceReader};
+use x509_cert::ext::pkix::{BasicConstraints, KeyUsage};
+
+// ecdsa-with-SHA256, the only signature algorithm we accept
+const ECDSA_WITH_SHA256: der::oid::ObjectIdentifier =
+    der::oid::ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+
+#[derive(Clone, Debug)]
+pub enum CertificateVerification {
+    // the presented chain has to end at one of these DER-encoded certificates,
+    // no anchors means every headset is refused
+    TrustAnchors(Vec<Vec<u8>>),
+    // accept any certificate, for headsets whose root we don't have, this
+    // leaves the session open to a man in the middle until one is pinned
+    Insecure,
+}
+
+impl Default for CertificateVerification {
+    fn default() -> Self {
+        CertificateVerification::TrustAnchors(Vec::new())
+    }
+}
+
+impl CertificateVerification {
+    // DER-encoded certificates out of a PEM bundle or a single DER file
+    pub fn load_anchors(data: &[u8]) -> Result<Vec<Vec<u8>>, HzospalError> {
+        if data.starts_with(b"-----BEGIN") {
+            return Certificate::load_pem_chain(data)
+                .map_err(|e| HzospalError::Certificate(format!("Bad trust anchor: {}", e)))?
+                .iter()
+                .map(|c| c.to_der().map_err(certificate_error))
+                .collect();
+        }
+
+        Certificate::from_der(data).map_err(certificate_error)?;
+        Ok(vec![data.to_vec()])
+    }
+
+    // checks that signature over message was made by the leaf of chain and
+    // that chain leads to a trust anchor, fails closed on anything unexpected
+    pub(crate) fn verify(
+        &self,
+        chain: Option<&[u8]>,
+        message: &[u8],
+        signature: Option<&[u8]>,
+    ) -> Result<(), HzospalError> {
+        let anchors = match self {
+            CertificateVerification::Insecure => return Ok(()),
+            CertificateVerification::TrustAnchors(anchors) if anchors.is_empty() => {
+                return Err(HzospalError::Certificate(
+                    "No trust anchors configured, refusing to trust the headset".into(),
+                ));
+            }
+            CertificateVerification::TrustAnchors(anchors) => anchors,
+        };
+
+        let chain = chain.ok_or_else(|| {
+            HzospalError::Certificate("HelloResponse was missing the server certificate".into())
+        })?;
+        let signature = signature.ok_or_else(|| {
+            HzospalError::Certificate("HelloResponse was missing the signature".into())
+        })?;
+
+        let chain = parse_chain(chain)?;
+        let leaf = &chain[0];
+
+        verify_signature(leaf, message, signature)
+            .map_err(|_| HzospalError::Certificate("HelloResponse signature is invalid".into()))?;
+
+        let now = SystemTime::now();
+        for (i, cert) in chain.iter().enumerate() {
+            check_validity(cert, now)?;
+
+            if let Some(issuer) = chain.get(i + 1) {
+                verify_issued_by(cert, issuer)?;
+            }
+        }
+
+        let last = chain.last().unwrap();
+        for anchor in anchors {
+            let anchor = Certificate::from_der(anchor).map_err(certificate_error)?;
+
+            if *last == anchor
+                || (last.tbs_certificate.issuer == anchor.tbs_certificate.subject
+                    && verify_issued_by(last, &anchor).is_ok())
+            {
+                return Ok(());
+            }
+        }
+
+        Err(HzospalError::Certificate(format!(
+            "Certificate chain for {} does not lead to a trust anchor",
+            leaf.tbs_certificate.subject
+        )))
+    }
+}
+
+// checks that signature over message was made by the key of the pinned
+// certificate, whether or not it's trusted otherwise
+pub(crate) fn verify_pinned(
+    pinned: &[u8],
+    message: &[u8],
+    signature: Option<&[u8]>,
+) -> Result<(), HzospalError> {
+    let signature = signature.ok_or_else(|| {
+        HzospalError::Certificate("HelloResponse was missing the signature".into())
+    })?;
+
+    verify_signature(&parse_chain(pinned)?[0], message, signature).map_err(|_| {
+        HzospalError::Certificate("HelloResponse was not signed by the pinned certificate".into())
+    })
+}
+
+// SHA-256 over the DER of the headset's own certificate, what
+// HelloRequest.known_cert_fingerprint carries
+pub fn fingerprint(chain: &[u8]) -> Result<[u8; 32], HzospalError> {
+    let leaf = parse_chain(chain)?[0].to_der().map_err(certificate_error)?;
+    Ok(Sha256::digest(leaf).into())
+}
+
+fn certificate_error(e: der::Error) -> HzospalError {
+    HzospalError::Certificate(format!("Malformed certificate: {}", e))
+}
+
+// the leaf first, followed by whatever intermediates the headset sends
+fn parse_chain(data: &[u8]) -> Result<Vec<Certificate>, HzospalError> {
+    let mut reader = SliceReader::new(data).map_err(certificate_error)?;
+    let mut chain = Vec::new();
+
+    while !reader.is_finished() {
+        chain.push(Certificate::decode(&mut reader).map_err(certificate_error)?);
+    }
+
+    if chain.is_empty() {
+        return Err(HzospalError::Certificate(
+            "Server certificate is empty".into(),
+        ));
+    }
+
+    Ok(chain)
+}
+
+fn verifying_key(cert: &Certificate) -> Result<VerifyingKey, HzospalError> {
+    let key = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .raw_bytes();
+    VerifyingKey::from_sec1_bytes(key).map_err(|_| {
+        HzospalError::Certificate(format!(
+            "{} does not hold a P-256 key",
+            cert.tbs_certificate.subject
+        ))
+    })
+}
+
+// accepts both DER and fixed size r || s signatures
+fn verify_signature(
+    cert: &Certificate,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), HzospalError> {
+    let key = verifying_key(cert)?;
+
+    let signature = match DerSignature::from_bytes(signature) {
+        Ok(signature) => Signature::try_from(signature),
+        Err(_) => Signature::from_slice(signature),
+    }
+    .map_err(|_| HzospalError::Certificate("Malformed signature".into()))?;
+
+    key.verify(message, &signature)
+        .map_err(|_| HzospalError::Certificate("Signature is invalid".into()))
+}
+
+fn verify_issued_by(cert: &Certificate, issuer: &Certificate) -> Result<(), HzospalError> {
+    if cert.signature_algorithm.oid != ECDSA_WITH_SHA256 {
+        return Err(HzospalError::Certificate(format!(
+            "{} is signed with unsupported algorithm {}",
+            cert.tbs_certificate.subject, cert.signature_algorithm.oid
+        )));
+    }
+
+    check_ca(issuer)?;
+
+    let tbs = cert.tbs_certificate.to_der().map_err(certificate_error)?;
+    let signature = cert.signature.as_bytes().ok_or_else(|| {
+        HzospalError::Certificate("Certificate signature is not byte aligned".into())
+    })?;
+
+    verify_signature(issuer, &tbs, signature).map_err(|_| {
+        HzospalError::Certificate(format!(
+            "{} was not issued by {}",
+            cert.tbs_certificate.subject, issuer.tbs_certificate.subject
+        ))
+    })
+}
+
+// a certificate may only sign others if it says it's a CA, keyUsage is
+// optional but has to allow keyCertSign when it's there
+fn check_ca(cert: &Certificate) -> Result<(), HzospalError> {
+    let not_a_ca = || {
+        HzospalError::Certificate(format!(
+            "{} is not a CA and can't issue certificates",
+            cert.tbs_certificate.subject
+        ))
+    };
+
+    let constraints = cert
+        .tbs_certificate
+        .get::<BasicConstraints>()
+        .map_err(certificate_error)?;
+    if !matches!(constraints, Some((_, BasicConstraints { ca: true, .. }))) {
+        return Err(not_a_ca());
+    }
+
+    let usage = cert
+        .tbs_certificate
+        .get::<KeyUsage>()
+        .map_err(certificate_error)?;
+    if let Some((_, usage)) = usage
+        && !usage.key_cert_sign()
+    {
+        return Err(not_a_ca());
+    }
+
+    Ok(())
+}
+
+fn check_validity(cert: &Certificate, now: SystemTime) -> Result<(), HzospalError> {
+    let validity = &cert.tbs_certificate.validity;
+
+    if now < validity.not_before.to_system_time() || now > validity.not_after.to_system_time() {
+        return Err(HzospalError::Certificate(format!(
+            "{} is not valid at this time",
+            cert.tbs_certificate.subject
+        )));
+    }
+
+    Ok(())
+}
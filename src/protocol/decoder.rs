@@ -1,14 +1,14 @@
+use crate::error::{DeviceError, HzospalError};
 use crate::{
-    QuestDevice,
     com::oculus::companion::server::{ErrorDetails, Response, ResponseCode},
+    protocol::dispatcher::PendingResponse,
 };
-use btleplug::api::Peripheral;
 use crypto_box::{SalsaBox, aead::Aead};
 use log::*;
 use prost::Message;
-use std::error::Error;
+use std::time::Duration;
 
-struct PacketAssembler {
+pub(crate) struct PacketAssembler {
     buffer: Vec<u8>,
     next_seq: u16,
 }
@@ -20,14 +20,14 @@ impl Default for PacketAssembler {
 }
 
 impl PacketAssembler {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             buffer: Vec::new(),
             next_seq: 0,
         }
     }
 
-    fn handle_notification(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+    pub(crate) fn handle_notification(&mut self, data: &[u8]) -> Option<Vec<u8>> {
         if data.len() < 2 {
             return None;
         }
@@ -72,75 +72,51 @@ impl PacketAssembler {
     }
 }
 
-// Do NOT use when device does not return a response, will Error
-pub async fn receive_protobuf<T: prost::Message + Default>(
-    quest: &QuestDevice,
-) -> Result<T, Box<dyn Error>> {
-    let mut assembler = PacketAssembler::new();
-    let start_time = std::time::Instant::now();
-
-    loop {
-        if start_time.elapsed().as_secs() > 30 {
-            return Err("Timeout waiting for response".into());
-        }
-
-        let data = quest.peripheral.read(&quest.ccs_characteristic).await?;
-
-        if data.is_empty() {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            continue;
-        }
-
-        // 0xFF means the Quest has no data to give at this time, so just wait
-        if data.len() == 1 && data[0] == 0xFF {
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-            continue;
-        }
-
-        if let Some(full_message) = assembler.handle_notification(&data) {
-            debug!("Reassembled message: {} bytes", full_message.len());
-
-            let decrypted_message = if let Some(crypto_box) = &quest.crypto_box {
-                if full_message.len() < 24 {
-                    return Err("Encrypted message too short".into());
-                }
-                let (nonce_bytes, ciphertext) = full_message.split_at(24);
-                let nonce = crypto_box::aead::Nonce::<SalsaBox>::from_slice(nonce_bytes);
-                crypto_box
-                    .decrypt(nonce, ciphertext)
-                    .map_err(|_| "Decryption failed")?
-            } else {
-                full_message
-            };
-
-            let response = Response::decode(&*decrypted_message)?;
-
-            let body = response.body.unwrap_or_default();
-            let seq = response
-                .seq
-                .ok_or("Response is corrupt or device did not return a response")?;
-            let code = ResponseCode::try_from(
-                response
-                    .code
-                    .ok_or("Response is corrupt or device did not return a response")?,
-            )?;
-
-            debug!("Response Code: {:#?}", code);
-            debug!("Response Seq: {:#?}", seq);
-
-            if code == ResponseCode::Fail || code == ResponseCode::FailRetry {
-                let msg = ErrorDetails::decode(&*body)?;
-                return Err(format!(
-                    "Packet exchanged errored, code {:#?}, details: {:#?}",
-                    code, msg
-                )
-                .into());
-            }
-
-            let msg = T::decode(&*body)?;
-            return Ok(msg);
+pub(crate) fn decode_response(
+    crypto_box: Option<&SalsaBox>,
+    full_message: &[u8],
+) -> Result<Response, HzospalError> {
+    let decrypted_message = if let Some(crypto_box) = crypto_box {
+        if full_message.len() < 24 {
+            return Err(HzospalError::Framing("Encrypted message too short".into()));
         }
+        let (nonce_bytes, ciphertext) = full_message.split_at(24);
+        let nonce = crypto_box::aead::Nonce::<SalsaBox>::from_slice(nonce_bytes);
+        crypto_box
+            .decrypt(nonce, ciphertext)
+            .map_err(|_| HzospalError::Crypto("Decryption failed".into()))?
+    } else {
+        full_message.to_vec()
+    };
+
+    Ok(Response::decode(&*decrypted_message)?)
+}
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+// Do NOT use when device does not return a response, will Error
+pub async fn receive_protobuf<T: prost::Message + Default>(
+    mut pending: PendingResponse,
+    timeout: Duration,
+) -> Result<T, HzospalError> {
+    let response = tokio::time::timeout(timeout, pending.response())
+        .await
+        .map_err(|_| {
+            HzospalError::Timeout(format!("waiting for response to seq {}", pending.seq))
+        })??;
+
+    let body = response.body.unwrap_or_default();
+    let corrupt =
+        || HzospalError::Protocol("Response is corrupt or device did not return a response".into());
+    let seq = response.seq.ok_or_else(corrupt)?;
+    let code = ResponseCode::try_from(response.code.ok_or_else(corrupt)?)?;
+
+    debug!("Response Code: {:#?}", code);
+    debug!("Response Seq: {:#?}", seq);
+
+    if code == ResponseCode::Fail || code == ResponseCode::FailRetry {
+        let details = ErrorDetails::decode(&*body)?;
+        return Err(DeviceError::from_details(code, details).into());
     }
+
+    let msg = T::decode(&*body)?;
+    Ok(msg)
 }
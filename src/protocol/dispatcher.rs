@@ -0,0 +1,291 @@
+use crate::error::HzospalError;
+use crate::{
+    com::oculus::companion::server::Response,
+    protocol::decoder::{PacketAssembler, decode_response},
+    transport::{FragmentStream, QuestTransport},
+};
+use crypto_box::SalsaBox;
+use futures::stream::StreamExt;
+use log::*;
+use prost::Message;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{Notify, oneshot};
+use tokio::task::JoinHandle;
+
+type Waiter = oneshot::Sender<Result<Response, HzospalError>>;
+
+// owns the read side of the link and hands every response to whoever sent
+// the request with the same seq
+pub(crate) struct Dispatcher {
+    pub(crate) crypto_box: RwLock<Option<SalsaBox>>,
+    pending: Mutex<HashMap<i32, Waiter>>,
+    // seqs that already got their response, a second one is a replay
+    consumed: Mutex<HashSet<i32>>,
+    // every nonce the headset sealed a frame with this session
+    nonces: Mutex<HashSet<[u8; 24]>>,
+    closed: Mutex<Option<String>>,
+    // the link dropped, as opposed to the dispatcher failing on its own
+    link_lost: AtomicBool,
+    wake: Notify,
+}
+
+// a request that was sent and is waiting for its response, dropping it
+// forgets the seq so a late response is discarded instead of misdelivered
+pub struct PendingResponse {
+    pub seq: i32,
+    receiver: oneshot::Receiver<Result<Response, HzospalError>>,
+    dispatcher: Arc<Dispatcher>,
+}
+
+impl PendingResponse {
+    pub(crate) async fn response(&mut self) -> Result<Response, HzospalError> {
+        match (&mut self.receiver).await {
+            Ok(result) => result,
+            Err(_) => Err(self.dispatcher.closed_error()),
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        self.dispatcher.pending.lock().unwrap().remove(&self.seq);
+    }
+}
+
+pub(crate) struct DispatcherTask(JoinHandle<()>, Arc<Dispatcher>);
+
+impl Drop for DispatcherTask {
+    fn drop(&mut self) {
+        self.0.abort();
+        // nobody is reading anymore, so don't leave waiters to time out
+        self.1.close("Session closed".to_string(), false);
+    }
+}
+
+impl Dispatcher {
+    pub(crate) fn spawn(
+        transport: Arc<dyn QuestTransport>,
+        notifications: Option<FragmentStream>,
+    ) -> (Arc<Self>, DispatcherTask) {
+        let dispatcher = Arc::new(Self {
+            crypto_box: RwLock::new(None),
+            pending: Mutex::new(HashMap::new()),
+            consumed: Mutex::new(HashSet::new()),
+            nonces: Mutex::new(HashSet::new()),
+            closed: Mutex::new(None),
+            link_lost: AtomicBool::new(false),
+            wake: Notify::new(),
+        });
+
+        let task = tokio::spawn(dispatcher.clone().run(transport, notifications));
+
+        (dispatcher.clone(), DispatcherTask(task, dispatcher))
+    }
+
+    pub(crate) fn register(self: &Arc<Self>, seq: i32) -> Result<PendingResponse, HzospalError> {
+        if self.is_closed() {
+            return Err(self.closed_error());
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(seq, sender);
+
+        Ok(PendingResponse {
+            seq,
+            receiver,
+            dispatcher: self.clone(),
+        })
+    }
+
+    // the request is fully written, start polling for its response
+    pub(crate) fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.lock().unwrap().is_some()
+    }
+
+    // set once the Hello exchange installed the session key, from then on
+    // nothing is accepted in plaintext
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.crypto_box.read().unwrap().is_some()
+    }
+
+    fn closed_error(&self) -> HzospalError {
+        if self.link_lost.load(Ordering::SeqCst) {
+            return HzospalError::NotConnected;
+        }
+
+        HzospalError::transport(
+            self.closed
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap_or_else(|| "Response dispatcher stopped".to_string()),
+        )
+    }
+
+    // fails every waiter, the first reason wins
+    fn close(&self, reason: String, link_lost: bool) {
+        {
+            let mut closed = self.closed.lock().unwrap();
+            if closed.is_some() {
+                return;
+            }
+            debug!("Dispatcher stopped: {}", reason);
+            self.link_lost.store(link_lost, Ordering::SeqCst);
+            *closed = Some(reason);
+        }
+
+        for (_, waiter) in self.pending.lock().unwrap().drain() {
+            let _ = waiter.send(Err(self.closed_error()));
+        }
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        transport: Arc<dyn QuestTransport>,
+        notifications: Option<FragmentStream>,
+    ) {
+        let read = async {
+            match notifications {
+                Some(stream) => self.run_notifications(stream).await,
+                None => self.run_polling(transport.as_ref()).await,
+            }
+        };
+
+        let (reason, link_lost) = tokio::select! {
+            result = read => match result {
+                // notifications stop when the link goes away
+                Ok(()) => ("Notification stream ended".to_string(), true),
+                Err(HzospalError::NotConnected) => ("Device disconnected".to_string(), true),
+                Err(e) => (format!("Failed to read from device: {}", e), false),
+            },
+            result = transport.disconnected() => match result {
+                Ok(()) => ("Device disconnected".to_string(), true),
+                Err(e) => (format!("Failed to watch the connection: {}", e), false),
+            },
+        };
+
+        self.close(reason, link_lost);
+    }
+
+    async fn run_notifications(&self, mut stream: FragmentStream) -> Result<(), HzospalError> {
+        let mut assembler = PacketAssembler::new();
+
+        while let Some(data) = stream.next().await {
+            if let Some(full_message) = assembler.handle_notification(&data) {
+                self.dispatch(&full_message);
+            }
+        }
+
+        Ok(())
+    }
+
+    // characteristic can't notify, so poll, but only while someone is waiting
+    async fn run_polling(&self, transport: &dyn QuestTransport) -> Result<(), HzospalError> {
+        let mut assembler = PacketAssembler::new();
+
+        loop {
+            if self.pending.lock().unwrap().is_empty() {
+                self.wake.notified().await;
+                continue;
+            }
+
+            let data = transport.read_fragment().await?;
+
+            if data.is_empty() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+
+            // 0xFF means the Quest has no data to give at this time, so just wait
+            if data.len() == 1 && data[0] == 0xFF {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                continue;
+            }
+
+            if let Some(full_message) = assembler.handle_notification(&data) {
+                self.dispatch(&full_message);
+                continue;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn dispatch(&self, full_message: &[u8]) {
+        debug!("Reassembled message: {} bytes", full_message.len());
+
+        let response = match self.unseal(full_message) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Dropping undecodable response: {}", e);
+                self.fail_plaintext(full_message);
+                return;
+            }
+        };
+
+        let Some(seq) = response.seq else {
+            warn!("Dropping response without a seq");
+            return;
+        };
+
+        if !self.consumed.lock().unwrap().insert(seq) {
+            warn!("Dropping replayed response for seq {}", seq);
+            return;
+        }
+
+        match self.pending.lock().unwrap().remove(&seq) {
+            Some(waiter) => {
+                let _ = waiter.send(Ok(response));
+            }
+            None => warn!("Dropping stale response for seq {}", seq),
+        }
+    }
+
+    // decrypts a frame sealed with the session key, or decodes it as is before
+    // the handshake, status pushes go through here too
+    pub(crate) fn unseal(&self, full_message: &[u8]) -> Result<Response, HzospalError> {
+        let crypto_box = self.crypto_box.read().unwrap();
+        let Some(crypto_box) = crypto_box.as_ref() else {
+            return decode_response(None, full_message);
+        };
+
+        let response = decode_response(Some(crypto_box), full_message)?;
+
+        // only authentic frames count, otherwise anyone could burn nonces
+        let nonce: [u8; 24] = full_message[..24].try_into().unwrap();
+        if !self.nonces.lock().unwrap().insert(nonce) {
+            return Err(HzospalError::Crypto(format!(
+                "Nonce {} was reused",
+                hex::encode(nonce)
+            )));
+        }
+
+        Ok(response)
+    }
+
+    // a plaintext response after the handshake is a downgrade attempt, fail
+    // the request it claims to answer instead of letting it time out
+    fn fail_plaintext(&self, full_message: &[u8]) {
+        if !self.is_encrypted() {
+            return;
+        }
+
+        let Some(seq) = Response::decode(full_message).ok().and_then(|r| r.seq) else {
+            return;
+        };
+
+        if let Some(waiter) = self.pending.lock().unwrap().remove(&seq) {
+            warn!("Refusing plaintext response for seq {}", seq);
+            let _ = waiter.send(Err(HzospalError::Crypto(
+                "Headset answered in plaintext after the handshake".into(),
+            )));
+        }
+    }
+}
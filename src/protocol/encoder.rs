@@ -1,18 +1,23 @@
+use crate::access::allowed_on_managed;
+use crate::error::HzospalError;
 use crate::{
     QuestDevice,
     com::oculus::companion::server::{Method, Request},
+    protocol::dispatcher::PendingResponse,
+    session::Session,
 };
-use btleplug::api::{Peripheral, WriteType};
 use crypto_box::{
     SalsaBox,
     aead::{Aead, AeadCore, OsRng},
 };
 use log::*;
 use prost::Message;
-use std::error::Error;
 
-fn fragment_message(data: &[u8], mtu: usize) -> Vec<Vec<u8>> {
-    let max_ble_data = mtu.saturating_sub(3);
+// the longest value an ATT attribute can hold, whatever the MTU
+const MAX_ATTRIBUTE_LEN: usize = 512;
+
+pub(crate) fn fragment_message(data: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+    let max_ble_data = mtu.saturating_sub(3).min(MAX_ATTRIBUTE_LEN);
     let payload_size = max_ble_data.saturating_sub(2);
 
     if payload_size == 0 {
@@ -48,13 +53,37 @@ fn fragment_message(data: &[u8], mtu: usize) -> Vec<Vec<u8>> {
 
 use std::sync::atomic::Ordering;
 
+// reconnects first if the link dropped and the device can reconnect
 pub async fn send_protobuf<T: prost::Message>(
     quest: &QuestDevice,
     protobuf: Option<T>,
     method: Method,
-) -> Result<(), Box<dyn Error>> {
-    if !quest.peripheral.is_connected().await? {
-        return Err("Device is not connected".into());
+) -> Result<PendingResponse, HzospalError> {
+    let session = quest.session().await?;
+    check_managed(&session, method)?;
+    send_on_session(quest, &session, protobuf, method).await
+}
+
+// only for what callers send, the handshake has to claim a managed headset
+// with OculusSetUserSecret like any other
+pub(crate) fn check_managed(session: &Session, method: Method) -> Result<(), HzospalError> {
+    if session.handshake.lock().unwrap().is_device_managed && !allowed_on_managed(method) {
+        return Err(HzospalError::Managed(format!(
+            "{:?} isn't allowed on a managed headset",
+            method
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) async fn send_on_session<T: prost::Message>(
+    quest: &QuestDevice,
+    session: &Session,
+    protobuf: Option<T>,
+    method: Method,
+) -> Result<PendingResponse, HzospalError> {
+    if !session.transport.is_connected().await? {
+        return Err(HzospalError::NotConnected);
     }
 
     let body = if let Some(proto) = protobuf {
@@ -82,30 +111,56 @@ pub async fn send_protobuf<T: prost::Message>(
     // Hello is the only unencrypted method
     let data_to_send = if method != Method::Hello {
         let nonce = SalsaBox::generate_nonce(&mut OsRng);
-        let mut ciphertext = quest
+        let mut ciphertext = session
+            .dispatcher
             .crypto_box
+            .read()
+            .unwrap()
             .as_ref()
-            .ok_or("No crypto_box")?
+            .ok_or_else(|| HzospalError::Crypto("No crypto_box".into()))?
             .encrypt(&nonce, &req_bytes[..])
-            .map_err(|_| "Encryption failed")?;
+            .map_err(|_| HzospalError::Crypto("Encryption failed".into()))?;
 
         let mut data = nonce.to_vec();
         data.append(&mut ciphertext);
         data
+    } else if session.dispatcher.is_encrypted() {
+        // a plaintext Hello would let the headset (or whoever answers) drop
+        // the session back to plaintext
+        return Err(HzospalError::Crypto(
+            "Session is already encrypted, refusing to send Hello".into(),
+        ));
     } else {
         req_bytes
     };
 
-    let packets = fragment_message(&data_to_send, 23);
-
-    debug!("Sending {:?}, {} packets", method, packets.len());
-
-    for p in packets.iter() {
-        quest
-            .peripheral
-            .write(&quest.ccs_characteristic, p, WriteType::WithResponse)
-            .await?;
-    }
-
-    Ok(())
+    let packets = fragment_message(&data_to_send, session.mtu);
+
+    debug!(
+        "Sending {:?} as seq {}, {} packets",
+        method,
+        seq,
+        packets.len()
+    );
+
+    // register before writing so a fast response can't beat us to it
+    let pending = session.dispatcher.register(seq)?;
+
+    // fragments of concurrent requests must not interleave on the wire, and a
+    // caller giving up halfway must not leave half a message on it either, so
+    // the whole message is written by a task that outlives the caller
+    let write_lock = session.write_lock.clone();
+    let transport = session.transport.clone();
+    let writer = tokio::spawn(async move {
+        let _write_guard = write_lock.lock_owned().await;
+        for p in packets.iter() {
+            transport.write_fragment(p).await?;
+        }
+        Ok::<(), HzospalError>(())
+    });
+
+    writer.await.map_err(HzospalError::transport)??;
+    session.dispatcher.wake();
+
+    Ok(pending)
 }
@@ -1,72 +1,144 @@
 use crate::{
     QuestDevice,
     com::oculus::companion::server::{
-        AuthenticateRequest, CombinedSetAccessTokenRequest, DevModeRequest, DevModeResponse,
-        HelloRequest, HelloResponse, HelloSignedData, HmdStatusResponse, Method,
-        OculusSetUserSecretRequest, OtaEnabledRequest, OtaEnabledResponse, SkipNuxAndLoginRequest,
-        SkipNuxAndLoginResponse, SkipNuxType, WifiConnectRequest,
+        AuthenticateRequest, CombinedSetAccessTokenRequest, DevModeRequest, HelloRequest,
+        HelloResponse, HelloSignedData, HmdStatusResponse, OculusSetUserSecretRequest,
+        OtaEnabledRequest, SkipNuxAndLoginRequest, SkipNuxType, WifiConnectRequest,
     },
-    protocol::{decoder::receive_protobuf, encoder::send_protobuf},
+    protocol::{
+        certificate::{fingerprint, verify_pinned},
+        decoder::receive_protobuf,
+        encoder::{check_managed, send_on_session},
+        rpc,
+    },
+    session::Session,
+    with_timeout,
 };
 
-pub use crate::com::oculus::companion::server::WifiAuthentication;
+use crate::access::HandshakeInfo;
+pub use crate::com::oculus::companion::server::{Method, WifiAuthentication};
+use crate::error::HzospalError;
 use crypto_box::{PublicKey, SalsaBox};
 use hmac::{Hmac, Mac};
 use log::*;
 use prost::Message;
 use rand::Rng;
 use sha2::Sha256;
-use std::error::Error;
+use std::time::{Duration, Instant};
 
 type HmacSha256 = Hmac<Sha256>;
 
-pub(crate) async fn say_hello(quest: &mut QuestDevice) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+pub(crate) struct Hello {
+    // only set if the headset is claimed
+    pub(crate) challenge: Option<Vec<u8>>,
+    // what the headset presented, or the pinned certificate if it recognised
+    // our fingerprint and didn't bother
+    pub(crate) certificate: Option<Vec<u8>>,
+    pub(crate) info: HandshakeInfo,
+}
+
+pub(crate) async fn say_hello(
+    quest: &QuestDevice,
+    session: &Session,
+) -> Result<Hello, HzospalError> {
     let mut client_challenge = vec![0u8; 16];
     rand::rng().fill_bytes(&mut client_challenge);
 
     let public_key_bytes = quest.x25519_keypair.1.to_vec();
 
+    let pinned = quest.server_certificate.as_deref();
+    let known_cert_fingerprint = pinned.map(fingerprint).transpose()?;
+
     let hello_request = HelloRequest {
         client_public_key: Some(public_key_bytes),
         client_challenge: Some(client_challenge),
+        known_cert_fingerprint: known_cert_fingerprint.map(|f| f.to_vec()),
         app_id: Some("com.oculus.companion.server".to_string()),
         app_version: Some("1.0.0".to_string()),
-        ..Default::default()
     };
 
     debug!("Sending HelloRequest...");
-    send_protobuf(quest, Some(hello_request), Method::Hello).await?;
+    let pending = send_on_session(quest, session, Some(hello_request), Method::Hello).await?;
 
     debug!("Waiting for HelloResponse...");
-    let hello_resp = receive_protobuf::<HelloResponse>(quest).await?;
+    let hello_resp = receive_protobuf::<HelloResponse>(pending, quest.timeouts.rpc).await?;
 
     let data = hello_resp
         .signed_data
-        .ok_or("HelloResponse was missing signed data")?;
+        .ok_or_else(|| HzospalError::Protocol("HelloResponse was missing signed data".into()))?;
+
+    let certificate = hello_resp
+        .server_certificate
+        .or_else(|| pinned.map(<[u8]>::to_vec));
+
+    // without the certificate the headset has to present it, we didn't send
+    // a fingerprint for it to recognise
+    let mut pin = known_cert_fingerprint.or(quest.pinned_fingerprint);
+    if let (Some(pinned), Some(presented)) = (pin, &certificate)
+        && pinned != fingerprint(presented)?
+    {
+        if !quest.accept_changed_certificate {
+            return Err(HzospalError::Certificate(format!(
+                "Certificate changed, pinned {} but the headset presented {}",
+                hex::encode(pinned),
+                hex::encode(fingerprint(presented)?)
+            )));
+        }
+        warn!("Headset certificate changed, accepting it anyway");
+        pin = None;
+    }
+
+    // the signature covers signed_data exactly, HelloSignedData has no field
+    // to echo client_challenge so a replayed HelloResponse still verifies
+    quest.certificate_verification.verify(
+        certificate.as_deref(),
+        &data,
+        hello_resp.signature.as_deref(),
+    )?;
+    // a pin holds whatever the verification mode, Insecure included, or an
+    // impostor could send the pinned certificate and sign with its own key
+    if pin.is_some() {
+        // the fingerprints matched, so this is the pinned certificate
+        let pinned = certificate.as_deref().ok_or_else(|| {
+            HzospalError::Certificate("HelloResponse was missing the pinned certificate".into())
+        })?;
+        verify_pinned(pinned, &data, hello_resp.signature.as_deref())?;
+    }
+    debug!("HelloResponse signature verified");
 
     let decoded = HelloSignedData::decode(&*data)?;
     debug!("Decoded signed HelloResponse: {:#?}", decoded);
 
     let server_public_key_bytes: [u8; 32] = decoded
         .server_public_key
-        .ok_or("Failed to decode server public key")?
+        .ok_or_else(|| HzospalError::Protocol("Failed to decode server public key".into()))?
         .try_into()
-        .map_err(|_| "Server public key is not 32 bytes")?;
+        .map_err(|_| HzospalError::Protocol("Server public key is not 32 bytes".into()))?;
     let server_public_key = PublicKey::from(server_public_key_bytes);
 
-    quest.crypto_box = Some(SalsaBox::new(&server_public_key, &quest.x25519_keypair.0));
+    *session.dispatcher.crypto_box.write().unwrap() =
+        Some(SalsaBox::new(&server_public_key, &quest.x25519_keypair.0));
 
     debug!("Encryption setup");
 
-    let auth_challenge = decoded.authentication_challenge;
+    let info = HandshakeInfo {
+        device_needs_to_be_unlocked: decoded.device_needs_to_be_unlocked.unwrap_or(false),
+        is_device_managed: decoded.is_device_managed.unwrap_or(false),
+    };
+    *session.handshake.lock().unwrap() = info;
 
-    Ok(auth_challenge)
+    Ok(Hello {
+        info,
+        challenge: decoded.authentication_challenge,
+        certificate,
+    })
 }
 
 pub(crate) async fn claim_device(
-    quest: &mut QuestDevice,
+    quest: &QuestDevice,
+    session: &Session,
     device_key: Option<[u8; 32]>,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<[u8; 32], HzospalError> {
     let device_key = device_key.unwrap_or_else(|| {
         let mut key = [0u8; 32];
         rand::fill(&mut key);
@@ -79,26 +151,27 @@ pub(crate) async fn claim_device(
         user_secret_key: Some(device_key.to_vec()),
     };
 
-    send_protobuf(quest, Some(claim_req), Method::OculusSetUserSecret).await?;
-
-    receive_protobuf::<()>(quest).await?;
+    let pending =
+        send_on_session(quest, session, Some(claim_req), Method::OculusSetUserSecret).await?;
 
-    quest.device_key = Some(device_key);
+    receive_protobuf::<()>(pending, quest.timeouts.rpc).await?;
 
+    // the key itself never goes to the log, logs get pasted into issues
     debug!(
-        "Claimed under {} (hex-encoded) device key, please backup or else you may have to reset your device!",
-        hex::encode(device_key)
+        "Claimed under a new device key, please back it up or else you may have to reset your device!"
     );
 
-    Ok(())
+    Ok(device_key)
 }
 
 pub(crate) async fn authenticate_device(
     quest: &QuestDevice,
+    session: &Session,
+    key: &[u8; 32],
     challenge: Vec<u8>,
-) -> Result<(), Box<dyn Error>> {
-    let key = quest.device_key.ok_or("Device key not set")?;
-    let mut hmac = HmacSha256::new_from_slice(&key)?;
+) -> Result<(), HzospalError> {
+    let mut hmac =
+        HmacSha256::new_from_slice(key).map_err(|e| HzospalError::Crypto(e.to_string()))?;
     hmac.update(&challenge);
     let signed_challenge = hmac.finalize().into_bytes().to_vec();
 
@@ -106,62 +179,135 @@ pub(crate) async fn authenticate_device(
         signed_authentication_challenge: Some(signed_challenge),
     };
 
-    send_protobuf(quest, Some(auth_req), Method::Authenticate).await?;
+    let pending = send_on_session(quest, session, Some(auth_req), Method::Authenticate).await?;
 
-    receive_protobuf::<()>(quest).await?;
+    receive_protobuf::<()>(pending, quest.timeouts.rpc).await?;
 
     Ok(())
 }
 
-pub async fn get_hmd_status(quest: &QuestDevice) -> Result<(), Box<dyn Error>> {
-    debug!("Asking for status...");
-    send_protobuf::<()>(quest, None, Method::HmdStatus).await?;
+// replaces the user secret of a claimed headset with new_key, then reconnects
+// to prove the headset took it, quest is left using whichever key works, the
+// old one if the headset refused the new one
+pub async fn rekey(quest: &mut QuestDevice, new_key: [u8; 32]) -> Result<(), HzospalError> {
+    let old_key = quest
+        .device_key
+        .ok_or_else(|| HzospalError::Crypto("Device key not set".into()))?;
+    if quest.connector.is_none() {
+        return Err(HzospalError::Protocol(
+            "Rekeying needs a link that can be re-established to verify the new key".into(),
+        ));
+    }
+
+    debug!("Installing a new device key...");
+
+    // not retried, a replay after the headset took the key would be refused
+    let session = quest.session().await?;
+    check_managed(&session, Method::OculusSetUserSecret)?;
+    let set_req = OculusSetUserSecretRequest {
+        user_secret_key: Some(new_key.to_vec()),
+    };
+    let result = async {
+        let pending =
+            send_on_session(quest, &session, Some(set_req), Method::OculusSetUserSecret).await?;
+        receive_protobuf::<()>(pending, quest.timeouts.rpc).await
+    }
+    .await;
+    drop(session);
+
+    // even if the answer got lost, only a handshake tells which key it holds
+    quest.device_key = Some(new_key);
+    let verified = quest.handshake_again().await;
+    if verified.is_ok() {
+        debug!("Headset accepted the new device key");
+        return Ok(());
+    }
 
-    debug!("Waiting for status...");
-    let status_resp = receive_protobuf::<HmdStatusResponse>(quest).await?;
+    quest.device_key = Some(old_key);
+    quest.handshake_again().await.map_err(|e| {
+        HzospalError::Protocol(format!(
+            "Headset took neither the new nor the old device key ({}), keep both",
+            e
+        ))
+    })?;
+
+    Err(result
+        .err()
+        .unwrap_or_else(|| HzospalError::Protocol("Headset kept its old device key".into())))
+}
+
+// a single Ping without retries, the outcome is recorded in quest.health()
+pub async fn ping(quest: &QuestDevice, timeout: Duration) -> Result<Duration, HzospalError> {
+    let result: Result<Duration, HzospalError> = async {
+        // reconnecting isn't part of the round trip
+        let session = quest.session().await?;
+
+        let started = Instant::now();
+        with_timeout(timeout, "ping", async {
+            let pending = send_on_session(quest, &session, None::<()>, Method::Ping).await?;
+            receive_protobuf::<()>(pending, timeout).await
+        })
+        .await?;
+
+        Ok(started.elapsed())
+    }
+    .await;
+
+    let mut health = quest.health.lock().unwrap();
+    match &result {
+        Ok(rtt) => {
+            health.healthy = true;
+            health.consecutive_failures = 0;
+            health.last_rtt = Some(*rtt);
+            health.last_success = Some(Instant::now());
+        }
+        Err(e) => {
+            health.consecutive_failures += 1;
+            health.last_failure = Some(e.to_string());
+        }
+    }
+
+    result
+}
+
+pub async fn get_hmd_status(quest: &QuestDevice) -> Result<HmdStatusResponse, HzospalError> {
+    debug!("Asking for status...");
+    let status_resp = quest.call::<rpc::HmdStatus>(()).await?;
 
     debug!("Status: {:#?}", status_resp);
 
-    Ok(())
+    Ok(status_resp)
 }
 
-pub async fn set_dev_mode(quest: &QuestDevice, mode: bool) -> Result<(), Box<dyn Error>> {
+pub async fn set_dev_mode(quest: &QuestDevice, mode: bool) -> Result<(), HzospalError> {
     let dev_req = DevModeRequest {
         mode: Some(mode.into()),
     };
     debug!("Asking to change dev mode to {}", mode);
-    send_protobuf(quest, Some(dev_req), Method::DevModeSet).await?;
-
     // this does not say whether it was changed
-    receive_protobuf::<()>(quest).await?;
+    quest.call::<rpc::DevModeSet>(dev_req).await?;
 
-    send_protobuf::<()>(quest, None, Method::DevModeStatus).await?;
-
-    let dev_resp = receive_protobuf::<DevModeResponse>(quest).await?;
+    let dev_resp = quest.call::<rpc::DevModeStatus>(()).await?;
 
     debug!("Dev mode is now: {:#?}", dev_resp.status);
 
     Ok(())
 }
 
-pub async fn set_ota_mode(quest: &QuestDevice, mode: bool) -> Result<(), Box<dyn Error>> {
+pub async fn set_ota_mode(quest: &QuestDevice, mode: bool) -> Result<(), HzospalError> {
     let ota_req = OtaEnabledRequest { enable: Some(mode) };
     debug!("Asking to change OTA mode to {}", mode);
-    send_protobuf(quest, Some(ota_req), Method::OtaEnabledSet).await?;
-
     // this does not say whether it was changed
-    receive_protobuf::<()>(quest).await?;
+    quest.call::<rpc::OtaEnabledSet>(ota_req).await?;
 
-    send_protobuf::<()>(quest, None, Method::OtaEnabledStatus).await?;
-
-    let ota_resp = receive_protobuf::<OtaEnabledResponse>(quest).await?;
+    let ota_resp = quest.call::<rpc::OtaEnabledStatus>(()).await?;
 
     debug!("OTA updates are now: {:#?}", ota_resp.enabled);
 
     Ok(())
 }
 
-pub async fn skip_nux(quest: &QuestDevice) -> Result<(), Box<dyn Error>> {
+pub async fn skip_nux(quest: &QuestDevice) -> Result<(), HzospalError> {
     let token_req = CombinedSetAccessTokenRequest {
         access_token_meta: Some("VEYGAX_HZOSPAL".into()),
         access_token_horizon_profile: Some("VEYGAX_HZOSPAL".into()),
@@ -170,12 +316,12 @@ pub async fn skip_nux(quest: &QuestDevice) -> Result<(), Box<dyn Error>> {
         ..Default::default()
     };
 
-    send_protobuf(quest, Some(token_req), Method::MetaSetAccessTokenCombined).await?;
-
     debug!("Waiting for token to be set");
-    let token_resp = receive_protobuf::<()>(quest).await?;
+    quest
+        .call::<rpc::MetaSetAccessTokenCombined>(token_req)
+        .await?;
 
-    debug!("Response to changing token: {:#?}", token_resp);
+    debug!("Token set");
 
     debug!("Skipping NUX...");
 
@@ -188,9 +334,7 @@ pub async fn skip_nux(quest: &QuestDevice) -> Result<(), Box<dyn Error>> {
         ..Default::default()
     };
 
-    send_protobuf(quest, Some(nux_req), Method::RetailSkipFirstTimeNux).await?;
-
-    receive_protobuf::<()>(quest).await?;
+    quest.call::<rpc::RetailSkipFirstTimeNux>(nux_req).await?;
 
     debug!("Waiting for NUX to finish skipping...");
 
@@ -200,9 +344,7 @@ pub async fn skip_nux(quest: &QuestDevice) -> Result<(), Box<dyn Error>> {
             ..Default::default()
         };
 
-        send_protobuf(quest, Some(nux_req), Method::RetailSkipFirstTimeNux).await?;
-
-        let resp = receive_protobuf::<SkipNuxAndLoginResponse>(quest).await?;
+        let resp = quest.call::<rpc::RetailSkipFirstTimeNux>(nux_req).await?;
         if resp.status == Some(0) {
             break;
         }
@@ -218,7 +360,7 @@ pub async fn connect_to_wifi(
     ssid: String,
     password: String,
     auth: WifiAuthentication,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), HzospalError> {
     let wifi_req = WifiConnectRequest {
         ssid: Some(ssid),
         password: Some(password),
@@ -227,9 +369,7 @@ pub async fn connect_to_wifi(
     };
 
     debug!("Connecting to WiFi...");
-    send_protobuf(quest, Some(wifi_req), Method::WifiConnect).await?;
-
-    receive_protobuf::<()>(quest).await?;
+    quest.call::<rpc::WifiConnect>(wifi_req).await?;
 
     debug!("Connected to WiFi!");
 
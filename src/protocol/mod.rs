@@ -1,3 +1,7 @@
+pub mod certificate;
 pub mod decoder;
+pub mod dispatcher;
 pub mod encoder;
 pub mod functions;
+pub mod retry;
+pub mod rpc;
@@ -0,0 +1,177 @@
+use crate::{
+    QuestDevice,
+    com::oculus::companion::server::{ErrorCode, Method, ResponseCode},
+    error::HzospalError,
+    protocol::{
+        decoder::receive_protobuf,
+        encoder::{check_managed, send_on_session},
+    },
+};
+use log::*;
+use std::collections::HashSet;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // total attempts including the first, 1 disables retrying
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub backoff_multiplier: u32,
+    // FAIL_RETRY is always retried, these codes are retried on FAIL too
+    pub retryable_codes: HashSet<ErrorCode>,
+    // transport errors and timeouts leave us not knowing whether the headset
+    // acted on the request, so they are only retried for idempotent methods
+    pub retry_transport_errors: bool,
+    pub idempotent_methods: HashSet<Method>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            backoff_multiplier: 2,
+            retryable_codes: HashSet::from([ErrorCode::AlreadyInProgress, ErrorCode::TimedOut]),
+            retry_transport_errors: true,
+            idempotent_methods: HashSet::from([
+                Method::Ping,
+                Method::WifiScan,
+                Method::WifiStatus,
+                Method::ControllerScan,
+                Method::ControllerStatus,
+                Method::PinStatus,
+                Method::PinReauthStatus,
+                Method::GetOtaStatus,
+                Method::DevModeSet,
+                Method::DevModeStatus,
+                Method::MtpModeSet,
+                Method::MtpModeStatus,
+                Method::AdbModeSet,
+                Method::AdbModeStatus,
+                Method::OtaEnabledSet,
+                Method::OtaEnabledStatus,
+                Method::CrashReportsEnabledSet,
+                Method::CrashReportsEnabledStatus,
+                Method::AutowakeSet,
+                Method::AutowakeStatus,
+                Method::AutosleepTimeSet,
+                Method::AutosleepTimeStatus,
+                Method::NameSet,
+                Method::LineFrequencySet,
+                Method::LineFrequencyStatus,
+                Method::HmdStatus,
+                Method::HmdVersion,
+                Method::HmdCapabilities,
+                Method::HmdExternalBatteryStatus,
+                Method::LocaleSet,
+                Method::TimeSet,
+                Method::ManagedModeStatus,
+                Method::CastingStatus,
+                Method::CastingDialogStatus,
+                Method::DevelopmentLicenseDetails,
+                Method::PhoneNotificationSet,
+                Method::PhoneNotificationStatus,
+                Method::PassAndTryStatus,
+                Method::ManagedDeviceStatus,
+                Method::ManagedAutoProvisioningStatus,
+                Method::ManagedAutoProvisioningAppsStatus,
+                Method::DeviceGenerationId,
+            ]),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_retryable(&self, method: Method, error: &HzospalError) -> bool {
+        match error {
+            HzospalError::Device(e) => {
+                e.response_code == ResponseCode::FailRetry || self.retryable_codes.contains(&e.code)
+            }
+            HzospalError::Transport(_) | HzospalError::Timeout(_) | HzospalError::NotConnected => {
+                self.retry_transport_errors && self.idempotent_methods.contains(&method)
+            }
+            _ => false,
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self
+            .backoff_multiplier
+            .saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+// send and wait for the response, re-sending under quest.retry_policy, every
+// attempt goes out with a fresh seq and nonce
+pub async fn request<T, R>(
+    quest: &QuestDevice,
+    protobuf: Option<T>,
+    method: Method,
+) -> Result<R, HzospalError>
+where
+    T: prost::Message + Clone,
+    R: prost::Message + Default,
+{
+    request_with_timeout(quest, protobuf, method, quest.timeouts.for_method(method)).await
+}
+
+// same as request, but each attempt waits at most timeout for its response
+pub async fn request_with_timeout<T, R>(
+    quest: &QuestDevice,
+    protobuf: Option<T>,
+    method: Method,
+    timeout: Duration,
+) -> Result<R, HzospalError>
+where
+    T: prost::Message + Clone,
+    R: prost::Message + Default,
+{
+    let policy = &quest.retry_policy;
+    let mut attempt = 1;
+
+    loop {
+        // reconnect failures aren't retried here, the reconnect policy has its
+        // own attempts and backoff
+        let session = quest.session().await?;
+
+        check_managed(&session, method)?;
+
+        let result = match send_on_session(quest, &session, protobuf.clone(), method).await {
+            Ok(pending) => receive_protobuf::<R>(pending, timeout).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            // the link dropped with the request in flight
+            Err(HzospalError::NotConnected) if !quest.reconnect_policy.replay_in_flight => {
+                return Err(HzospalError::NotConnected);
+            }
+            Err(e) if attempt < policy.max_attempts && policy.is_retryable(method, &e) => {
+                let backoff = policy.backoff(attempt);
+                warn!(
+                    "{:?} failed ({}), retrying in {:?} (attempt {}/{})",
+                    method,
+                    e,
+                    backoff,
+                    attempt + 1,
+                    policy.max_attempts
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
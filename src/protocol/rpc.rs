@@ -0,0 +1,284 @@
+use crate::{
+    QuestDevice,
+    com::oculus::companion::server::{self, Method},
+    error::HzospalError,
+    protocol::retry,
+};
+#[cfg(feature = "serde")]
+use futures::future::BoxFuture;
+#[cfg(feature = "serde")]
+use serde_json::Value;
+
+// what QuestRpc requests and responses have to be, with the serde feature
+// they also (de)serialize so call_json can handle any of them
+#[cfg(feature = "serde")]
+pub trait RpcMessage:
+    prost::Message + Default + serde::Serialize + serde::de::DeserializeOwned + 'static
+{
+}
+#[cfg(feature = "serde")]
+impl<T> RpcMessage for T where
+    T: prost::Message + Default + serde::Serialize + serde::de::DeserializeOwned + 'static
+{
+}
+
+#[cfg(not(feature = "serde"))]
+pub trait RpcMessage: prost::Message + Default + 'static {}
+#[cfg(not(feature = "serde"))]
+impl<T> RpcMessage for T where T: prost::Message + Default + 'static {}
+
+// ties a Method to the messages it's sent with and answered with, one unit
+// struct per Method below, named after it
+pub trait QuestRpc {
+    const METHOD: Method;
+    // false for methods that take no request message, those go out without a
+    // body and Request is ()
+    const SENDS_BODY: bool;
+    type Request: RpcMessage + Clone;
+    // () for methods that only answer with a response code
+    type Response: RpcMessage;
+}
+
+// for code that only knows the Method at runtime, visit calls visitor.visit
+// with the QuestRpc type of the method
+pub trait MethodVisitor {
+    type Output;
+    fn visit<M: QuestRpc>(self) -> Self::Output;
+}
+
+impl QuestDevice {
+    // send M under the retry policy and timeouts, and decode its response
+    pub async fn call<M: QuestRpc>(
+        &self,
+        request: M::Request,
+    ) -> Result<M::Response, HzospalError> {
+        self.check_supported(M::METHOD)?;
+
+        let body = M::SENDS_BODY.then_some(request);
+        let result = retry::request::<_, M::Response>(self, body, M::METHOD).await;
+        self.record_support(M::METHOD, &result);
+        result
+    }
+}
+
+#[cfg(feature = "serde")]
+impl QuestDevice {
+    // call for a Method picked at runtime, the request is built from JSON
+    // (None sends an empty one) and the response comes back as JSON
+    pub async fn call_json(
+        &self,
+        method: Method,
+        request: Option<Value>,
+    ) -> Result<Value, HzospalError> {
+        struct CallJson<'a> {
+            quest: &'a QuestDevice,
+            request: Option<Value>,
+        }
+
+        impl<'a> MethodVisitor for CallJson<'a> {
+            type Output = BoxFuture<'a, Result<Value, HzospalError>>;
+
+            fn visit<M: QuestRpc>(self) -> Self::Output {
+                Box::pin(async move {
+                    let request = match self.request {
+                        // {} is as good as nothing for methods without a body
+                        Some(Value::Object(map)) if map.is_empty() && !M::SENDS_BODY => {
+                            M::Request::default()
+                        }
+                        Some(request) => serde_json::from_value(request).map_err(|e| {
+                            HzospalError::BadRequest(format!("{:?}: {}", M::METHOD, e))
+                        })?,
+                        None => M::Request::default(),
+                    };
+
+                    let response = self.quest.call::<M>(request).await?;
+                    serde_json::to_value(response)
+                        .map_err(|e| HzospalError::Protocol(format!("{:?}: {}", M::METHOD, e)))
+                })
+            }
+        }
+
+        visit(
+            method,
+            CallJson {
+                quest: self,
+                request,
+            },
+        )
+        .ok_or_else(|| HzospalError::BadRequest(format!("{:?} can't be called", method)))?
+        .await
+    }
+}
+
+macro_rules! rpc {
+    ($($method:ident $(($request:ty))? => $response:ty,)*) => {
+        $(
+            pub struct $method;
+
+            impl QuestRpc for $method {
+                const METHOD: Method = Method::$method;
+                const SENDS_BODY: bool = rpc!(@sends_body $($request)?);
+                type Request = rpc!(@request $($request)?);
+                type Response = $response;
+            }
+        )*
+
+        // None for METHOD_UNKNOWN
+        pub fn visit<V: MethodVisitor>(method: Method, visitor: V) -> Option<V::Output> {
+            match method {
+                $(Method::$method => Some(visitor.visit::<$method>()),)*
+                Method::Unknown => None,
+            }
+        }
+    };
+    (@sends_body) => { false };
+    (@sends_body $request:ty) => { true };
+    (@request) => { () };
+    (@request $request:ty) => { $request };
+}
+
+// Protocol.proto doesn't say which message goes with which method, this
+// follows the message names, Ax*Request methods set a setting and answer
+// with it, Ax*Response methods read it back, METHOD_UNKNOWN isn't a method
+rpc! {
+    Ping => (),
+    Hello(server::HelloRequest) => server::HelloResponse,
+    Authenticate(server::AuthenticateRequest) => (),
+
+    WifiScan => server::WifiScanResponse,
+    WifiConnect(server::WifiConnectRequest) => (),
+    WifiStatus => server::WifiStatusResponse,
+    WifiForget(server::WifiForgetRequest) => (),
+    WifiEnable => (),
+    WifiDisable => (),
+    WifiReconnect(server::WifiReconnectRequest) => (),
+
+    OculusLoginDeprecated(server::OculusLoginRequest) => (),
+    OculusLogout => (),
+    OculusSetUserSecret(server::OculusSetUserSecretRequest) => (),
+    OculusSetAccessToken(server::OculusSetAccessTokenRequest) => (),
+    OculusSecondaryAccountLogin(server::OculusSecondaryAccountLoginRequest) => (),
+    OculusInsertLinkedAccountDeprecated => (),
+    OculusSecondaryUserAdd => (),
+    OculusSecondaryUserRemove(server::OculusSecondaryUserRemoveRequest) => (),
+    OculusSecondaryUserGetDeviceCode => server::OculusSecondaryUserGetDeviceCoderesponse,
+    MetaSetAccessToken(server::MetaSetAccessTokenRequest) => (),
+    MetaSetAccessTokenCombined(server::CombinedSetAccessTokenRequest) => (),
+    MetaSecondaryAccountLogin(server::MetaSecondaryAccountLoginRequest) => (),
+
+    ControllerScan(server::ControllerScanRequest) => server::ControllerScanResponse,
+    ControllerPair(server::ControllerPairRequest) => (),
+    ControllerStatus(server::ControllerStatusRequest) => server::ControllerStatusResponse,
+    ControllerUnpair(server::ControllerUnpairRequest) => (),
+    ControllerSetHandedness(server::ControllerSetHandednessRequest) => (),
+    ControllerScanAndPair(server::ControllerScanAndPairRequest) => (),
+    ControllerVerifyConnectable(server::ControllerVerifyConnectableRequest) => server::ControllerDetectionState,
+    VerifyMultipleControllersConnectable(server::VerifyMultipleControllersConnectableRequest) => server::VerifyMultipleControllersConnectableResponse,
+    ControllerCheckForUpdate(server::ControllerCheckForUpdateRequest) => (),
+
+    PinSet(server::PinSetRequest) => (),
+    PinStatus => server::PinStatusResponse,
+    PinLock => (),
+    PinUnlock(server::PinUnlockRequest) => server::PinUnlockResponse,
+    PinVerify(server::PinVerifyRequest) => server::PinVerifyResponse,
+    PinReset(server::SystemUnlockRequest) => (),
+    PinReauthStatus => server::PinReauthStatusResponse,
+
+    WipeData(server::WipeDataRequest) => (),
+    OtaCheckAvailability(server::OtaCheckAvailabilityRequest) => server::OtaCheckAvailabilityResponse,
+    OtaManualUpdate(server::OtaManualUpdateRequest) => (),
+    RebootUpgrade => (),
+    GetOtaStatus => server::GetOtaStatusResponse,
+
+    DevModeSet(server::DevModeRequest) => (),
+    DevModeStatus => server::DevModeResponse,
+    MtpModeSet(server::MtpModeRequest) => (),
+    MtpModeStatus => server::MtpModeResponse,
+    AdbModeSet(server::AdbModeRequest) => (),
+    AdbModeStatus => server::AdbModeResponse,
+    OtaEnabledSet(server::OtaEnabledRequest) => (),
+    OtaEnabledStatus => server::OtaEnabledResponse,
+    CrashReportsEnabledSet(server::CrashReportsEnabledRequest) => (),
+    CrashReportsEnabledStatus => server::CrashReportsEnabledResponse,
+    AutowakeSet(server::AutowakeRequest) => (),
+    AutowakeStatus => server::AutowakeResponse,
+    AutosleepTimeSet(server::AutosleepTimeRequest) => (),
+    AutosleepTimeStatus => server::AutosleepTimeResponse,
+    NameSet(server::NameSetRequest) => (),
+    LineFrequencySet(server::LineFrequencyRequest) => (),
+    LineFrequencyStatus => server::LineFrequencyResponse,
+    StartWirelessPairingServer => server::StartWirelessPairingServerResponse,
+    GetWirelessPairingDetails => server::StartWirelessPairingServerResponse,
+    RebootDevice(server::RebootDeviceRequest) => (),
+
+    AppLaunch(server::AppLaunchRequest) => server::AppLaunchResponse,
+    AppHandleRemoteOperations => (),
+
+    HmdStatus => server::HmdStatusResponse,
+    HmdVersion => server::HmdVersionResponse,
+    HmdCapabilities => server::HmdCapabilitiesResponse,
+    HmdExternalBatteryStatus => server::HmdExternalBatteryStatusResponse,
+
+    LocaleSet(server::LocaleSet) => (),
+    TimeSet(server::TimeSet) => (),
+    HealthAndSafetyWarningSet(server::HealthAndSafetyWarningRequest) => (),
+    SyncBackupPin(server::SyncBackupPinRequest) => (),
+    NuxCompleted => (),
+    NuxSetTwfExperimentGroup(server::NuxSetTwfExperimentGroupRequest) => (),
+    MirrorRequest(server::MirrorRequest) => server::MirrorResponse,
+    ManagedModeSet(server::ManagedModeSet) => (),
+    ManagedModeStatus => server::ManagedModeResponse,
+    TextSend(server::TextSend) => (),
+    CloudSyncSignalSend(server::CloudSyncSignalSend) => (),
+    CloudMediaOneTimeSync => (),
+
+    DiscoverCastingDevices => server::DiscoverCastingDevicesResponse,
+    StartCasting(server::StartCastingRequest) => server::StartCastingResponse,
+    StopCasting => (),
+    CastingStatus => server::CastingStatusResponse,
+    CastingDialogStatus => server::CastingDialogStatusResponse,
+
+    SkipHighPriAppsDownload => (),
+    RetailSkipFirstTimeNux(server::SkipNuxAndLoginRequest) => server::SkipNuxAndLoginResponse,
+    ResetGuardian => (),
+    ResetHeadsetView => (),
+    DevelopmentLicenseDetails => server::DevelopmentLicenseDetailsResponse,
+    DevelopmentLicenseRefresh => (),
+    PhoneNotificationSet(server::PhoneNotificationRequest) => (),
+    PhoneNotificationStatus => server::PhoneNotificationResponse,
+    InputEventSend(server::InputEventSend) => (),
+    TogglePassthrough => (),
+    ChangeBatteryLedTemporarily(server::ChangeBatteryLedTempRequest) => (),
+    PassAndTryStatus => server::PassAndTryStatusResponse,
+    ManagedDeviceStatus => server::ManagedDeviceStatusResponse,
+
+    AxMonoAudioRequest(server::MonoAudioRequest) => server::MonoAudioResponse,
+    AxMonoAudioResponse => server::MonoAudioResponse,
+    AxAudioBalanceRequest(server::AudioBalanceRequest) => server::AudioBalanceResponse,
+    AxAudioBalanceResponse => server::AudioBalanceResponse,
+    AxGetEnabledSettingsByCategory(server::AxGetEnabledSettingsByCategoryRequest) => server::AxGetEnabledSettingsByCategoryResponse,
+    AxUpdateSetting(server::AxUpdateSettingValueRequest) => (),
+    AxFontSizeRequest(server::FontSizeRequest) => server::FontSizeResponse,
+    AxFontSizeResponse => server::FontSizeResponse,
+    AxColorCorrectionEnabledRequest(server::ColorCorrectionEnabledRequest) => server::ColorCorrectionEnabledResponse,
+    AxColorCorrectionEnabledResponse => server::ColorCorrectionEnabledResponse,
+    AxColorCorrectionValueRequest(server::ColorCorrectionValueRequest) => server::ColorCorrectionValueResponse,
+    AxColorCorrectionValueResponse => server::ColorCorrectionValueResponse,
+    AxDisplayContrastRequest(server::DisplayContrastRequest) => server::DisplayContrastResponse,
+    AxDisplayContrastResponse => server::DisplayContrastResponse,
+    AxControllerVibrationRequest(server::ControllerVibrationRequest) => server::ControllerVibrationResponse,
+    AxControllerVibrationResponse => server::ControllerVibrationResponse,
+    AxSwitchOculusMenuButtonRequest(server::SwitchOculusMenuButtonRequest) => server::SwitchOculusMenuButtonResponse,
+    AxSwitchOculusMenuButtonResponse => server::SwitchOculusMenuButtonResponse,
+    AxBoostHeightRequest(server::BoostHeightRequest) => server::BoostHeightResponse,
+    AxBoostHeightResponse => server::BoostHeightResponse,
+
+    ManagedAutoProvisioningStart(server::ManagedAutoProvisioningStartRequest) => (),
+    ManagedAutoProvisioningStatus => server::ManagedAutoProvisioningStatusResponse,
+    ManagedAutoProvisioningAppsStatus => server::ManagedAutoProvisioningAppsStatusResponse,
+    ManagedAutoProvisioningFinish(server::ManagedAutoProvisioningFinishRequest) => (),
+    LocationSignal(server::LocationSignal) => (),
+    ManagedAutoProvisioningPrepare => (),
+    SecondaryUserSupportedMethodsResponse => server::SecondaryUserSupportedMethodsResponse,
+    DeviceGenerationId => server::DeviceGenerationIdResponse,
+}
@@ -0,0 +1,405 @@
+use crate::{
+    ConnectionOptions, QuestDevice,
+    adapter::{AdapterInfo, AdapterSelector, connection_count, select_adapters},
+    error::HzospalError,
+    open_quest,
+    session::Connector,
+    transport::{BtleTransport, QuestTransport},
+};
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, Peripheral as _, PeripheralProperties, ScanFilter,
+};
+use btleplug::platform::{Adapter, Peripheral, PeripheralId};
+use futures::stream::{BoxStream, SelectAll, StreamExt, select_all};
+use log::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::{Uuid, uuid};
+
+// every Quest advertises this service while it's not connected
+pub const QUEST_UUID: Uuid = uuid!("0000feb8-0000-1000-8000-00805f9b34fb");
+
+#[derive(Clone, Debug)]
+pub struct DiscoveredQuest {
+    // index of the adapter that saw it, see adapter::list_adapters
+    pub adapter: usize,
+    pub id: PeripheralId,
+    pub address: BDAddr,
+    pub local_name: Option<String>,
+    pub rssi: Option<i16>,
+    pub tx_power_level: Option<i16>,
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    pub services: Vec<Uuid>,
+}
+
+impl DiscoveredQuest {
+    fn from_properties(
+        adapter: usize,
+        id: PeripheralId,
+        properties: PeripheralProperties,
+    ) -> Option<Self> {
+        if !properties.services.contains(&QUEST_UUID) {
+            return None;
+        }
+
+        Some(Self {
+            adapter,
+            id,
+            address: properties.address,
+            local_name: properties.local_name,
+            rssi: properties.rssi,
+            tx_power_level: properties.tx_power_level,
+            manufacturer_data: properties.manufacturer_data,
+            service_data: properties.service_data,
+            services: properties.services,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.local_name.as_deref().unwrap_or("Unknown")
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum QuestSelector {
+    // whichever headset shows up first
+    #[default]
+    Any,
+    Id(PeripheralId),
+    Address(BDAddr),
+    // case-insensitive, * and ? wildcards
+    Name(String),
+}
+
+impl QuestSelector {
+    pub fn matches(&self, quest: &DiscoveredQuest) -> bool {
+        match self {
+            QuestSelector::Any => true,
+            QuestSelector::Id(id) => &quest.id == id,
+            QuestSelector::Address(address) => &quest.address == address,
+            QuestSelector::Name(pattern) => quest
+                .local_name
+                .as_deref()
+                .is_some_and(|name| wildcard_match(pattern, name)),
+        }
+    }
+}
+
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+async fn discovered(
+    (info, central): &(AdapterInfo, Adapter),
+    id: &PeripheralId,
+) -> Option<DiscoveredQuest> {
+    let peripheral = central.peripheral(id).await.ok()?;
+    let properties = peripheral.properties().await.ok()??;
+    DiscoveredQuest::from_properties(info.index, id.clone(), properties)
+}
+
+// the adapters still scanning, stopped in the background if the future
+// scanning is dropped before it could stop them itself
+struct Scans {
+    centrals: Vec<Adapter>,
+}
+
+impl Scans {
+    // one at a time, so drop still stops whatever a failure leaves scanning
+    async fn stop(mut self) -> Result<(), HzospalError> {
+        while let Some(central) = self.centrals.last() {
+            central.stop_scan().await?;
+            self.centrals.pop();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Scans {
+    fn drop(&mut self) {
+        let centrals = std::mem::take(&mut self.centrals);
+        if centrals.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("Scan cancelled outside a runtime, adapters keep scanning");
+            return;
+        };
+
+        debug!("Scan cancelled, stopping it");
+        runtime.spawn(async move {
+            for central in centrals {
+                if let Err(e) = central.stop_scan().await {
+                    warn!("Failed to stop scanning: {}", e);
+                }
+            }
+        });
+    }
+}
+
+// every adapter's events, tagged with its position in adapters
+async fn start_scans(
+    adapters: &[(AdapterInfo, Adapter)],
+) -> Result<(SelectAll<BoxStream<'static, (usize, CentralEvent)>>, Scans), HzospalError> {
+    let mut events = Vec::with_capacity(adapters.len());
+    // whatever started before a failure is stopped again
+    let mut scans = Scans {
+        centrals: Vec::with_capacity(adapters.len()),
+    };
+    for (i, (_, central)) in adapters.iter().enumerate() {
+        events.push(central.events().await?.map(move |e| (i, e)).boxed());
+        central.start_scan(ScanFilter::default()).await?;
+        scans.centrals.push(central.clone());
+    }
+    Ok((select_all(events), scans))
+}
+
+// lists every headset advertising during duration, without connecting
+pub async fn scan_quests(duration: Duration) -> Result<Vec<DiscoveredQuest>, HzospalError> {
+    scan_quests_on(&AdapterSelector::default(), duration).await
+}
+
+// same as scan_quests, a headset seen by several adapters is listed once with
+// the strongest signal
+pub async fn scan_quests_on(
+    adapter: &AdapterSelector,
+    duration: Duration,
+) -> Result<Vec<DiscoveredQuest>, HzospalError> {
+    let adapters = select_adapters(adapter).await?;
+    let (mut events, scans) = start_scans(&adapters).await?;
+
+    let mut quests: HashMap<BDAddr, DiscoveredQuest> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + duration;
+
+    while let Ok(Some((i, event))) = tokio::time::timeout_at(deadline, events.next()).await {
+        let id = match event {
+            CentralEvent::DeviceDiscovered(id) => id,
+            CentralEvent::DeviceUpdated(id) => id,
+            _ => continue,
+        };
+
+        let Some(quest) = discovered(&adapters[i], &id).await else {
+            continue;
+        };
+
+        match quests.get(&quest.address) {
+            None => {
+                debug!(
+                    "Found {} ({}) on {}",
+                    quest.name(),
+                    quest.address,
+                    adapters[i].0.name()
+                );
+            }
+            Some(seen) if seen.adapter != quest.adapter && seen.rssi >= quest.rssi => continue,
+            Some(_) => {}
+        }
+        quests.insert(quest.address, quest);
+    }
+
+    scans.stop().await?;
+
+    let mut quests: Vec<DiscoveredQuest> = quests.into_values().collect();
+    quests.sort_by_key(|q| std::cmp::Reverse(q.rssi.unwrap_or(i16::MIN)));
+
+    Ok(quests)
+}
+
+// scans until a headset matching selector shows up, then connects and runs
+// the handshake with it
+pub async fn connect(
+    selector: QuestSelector,
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<Option<QuestDevice>, HzospalError> {
+    let adapters = select_adapters(&options.adapter).await?;
+
+    let Some(quest) = find(&adapters, &selector, options.timeouts.scan).await? else {
+        return Ok(None);
+    };
+
+    debug!(
+        "Found {} ({}), {} RSSI",
+        quest.name(),
+        quest.address,
+        quest.rssi.unwrap_or(0)
+    );
+
+    let name = quest.name().to_string();
+    let address = quest.address.to_string();
+    let timeouts = options.timeouts.clone();
+
+    let connector: Connector = Arc::new(move || {
+        let adapters = adapters.clone();
+        let quest = quest.clone();
+        let timeouts = timeouts.clone();
+
+        Box::pin(async move {
+            let (central, peripheral) = match least_busy(&adapters, &quest).await {
+                Ok(found) => found,
+                // forgotten by every adapter, e.g. after a reboot, so wait for
+                // it to advertise again
+                Err(_) => {
+                    let selector = QuestSelector::Address(quest.address);
+                    let found = find(&adapters, &selector, Some(timeouts.reconnect_scan))
+                        .await?
+                        .ok_or_else(|| {
+                            HzospalError::Timeout("waiting for the headset to reappear".into())
+                        })?;
+                    least_busy(&adapters, &found).await?
+                }
+            };
+
+            let transport = BtleTransport::connect(central, peripheral, &timeouts).await?;
+            Ok(Box::new(transport) as Box<dyn QuestTransport>)
+        })
+    });
+
+    let transport = connector().await?;
+    let quest = open_quest(
+        transport,
+        Some(connector),
+        name,
+        Some(address),
+        device_key,
+        options,
+    )
+    .await?;
+
+    Ok(Some(quest))
+}
+
+async fn find(
+    adapters: &[(AdapterInfo, Adapter)],
+    selector: &QuestSelector,
+    timeout: Option<Duration>,
+) -> Result<Option<DiscoveredQuest>, HzospalError> {
+    // already known to an adapter, no need to wait for an advertisement
+    if let QuestSelector::Id(id) = selector {
+        for adapter in adapters {
+            if let Some(quest) = discovered(adapter, id).await {
+                return Ok(Some(quest));
+            }
+        }
+    }
+
+    let (mut events, scans) = start_scans(adapters).await?;
+
+    let deadline = timeout.map(|scan| tokio::time::Instant::now() + scan);
+    let mut found = None;
+
+    loop {
+        let event = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, events.next()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    debug!("No matching headset found before the scan timeout");
+                    break;
+                }
+            },
+            None => events.next().await,
+        };
+
+        let Some((i, event)) = event else {
+            break;
+        };
+
+        let id = match event {
+            CentralEvent::DeviceDiscovered(id) => id,
+            CentralEvent::DeviceUpdated(id) => id,
+            _ => continue,
+        };
+
+        if let Some(quest) = discovered(&adapters[i], &id).await
+            && selector.matches(&quest)
+        {
+            found = Some(quest);
+            break;
+        }
+    }
+
+    scans.stop().await?;
+
+    Ok(found)
+}
+
+// of the adapters that can see the headset, the one with the fewest
+// connections, so provisioning several headsets spreads across dongles
+async fn least_busy(
+    adapters: &[(AdapterInfo, Adapter)],
+    quest: &DiscoveredQuest,
+) -> Result<(Adapter, Peripheral), HzospalError> {
+    let mut best: Option<(usize, Adapter, Peripheral)> = None;
+
+    for (info, central) in adapters {
+        let peripheral = if info.index == quest.adapter {
+            central.peripheral(&quest.id).await.ok()
+        } else {
+            let mut found = None;
+            for p in central.peripherals().await.unwrap_or_default() {
+                if p.address() == quest.address {
+                    found = Some(p);
+                    break;
+                }
+            }
+            found
+        };
+
+        let Some(peripheral) = peripheral else {
+            continue;
+        };
+
+        let connections = connection_count(central).await;
+        if best.as_ref().is_none_or(|(c, _, _)| connections < *c) {
+            best = Some((connections, central.clone(), peripheral));
+        }
+    }
+
+    let (connections, central, peripheral) =
+        best.ok_or_else(|| HzospalError::transport("Headset disappeared before connecting"))?;
+    debug!(
+        "Connecting through the adapter with {} connections",
+        connections
+    );
+
+    Ok((central, peripheral))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildcard_match;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("Quest 3", "quest 3"));
+        assert!(wildcard_match("quest*", "Quest 3S"));
+        assert!(wildcard_match("*3?", "Quest 3S"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("quest", "Quest 3"));
+        assert!(!wildcard_match("*pro", "Quest 3"));
+    }
+}
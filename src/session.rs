@@ -0,0 +1,243 @@
+use crate::access::{HandshakeInfo, unlock};
+use crate::error::HzospalError;
+use crate::keepalive::LinkHealth;
+use crate::protocol::dispatcher::{Dispatcher, DispatcherTask};
+use crate::protocol::functions::{authenticate_device, say_hello};
+use crate::transport::QuestTransport;
+use crate::{DEFAULT_MTU, QuestDevice, with_timeout};
+use futures::future::BoxFuture;
+use log::*;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// opens a fresh link to the same headset, called whenever the current one drops
+pub type Connector = Arc<
+    dyn Fn() -> BoxFuture<'static, Result<Box<dyn QuestTransport>, HzospalError>> + Send + Sync,
+>;
+
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    // attempts per reconnect, 0 disables reconnecting
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // re-send the request that was in flight when the link dropped, only if
+    // retry_policy considers its method idempotent
+    pub replay_in_flight: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            replay_in_flight: true,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 0,
+            ..Default::default()
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+// everything that lives exactly as long as one link, a reconnect replaces it
+// as a whole so no reassembly or encryption state survives a drop
+pub(crate) struct Session {
+    pub(crate) transport: Arc<dyn QuestTransport>,
+    pub(crate) dispatcher: Arc<Dispatcher>,
+    pub(crate) mtu: usize,
+    pub(crate) write_lock: Arc<tokio::sync::Mutex<()>>,
+    // what the headset said in its hello on this link
+    pub(crate) handshake: Mutex<HandshakeInfo>,
+    _dispatcher_task: DispatcherTask,
+}
+
+impl Session {
+    pub(crate) async fn open(
+        transport: Box<dyn QuestTransport>,
+        mtu: Option<usize>,
+    ) -> Result<Arc<Self>, HzospalError> {
+        let mtu = match mtu {
+            // nothing would fit in a fragment, every call would just time out
+            Some(mtu) if mtu < DEFAULT_MTU => {
+                return Err(HzospalError::Framing(format!(
+                    "MTU override of {} is below the BLE minimum of {}",
+                    mtu, DEFAULT_MTU
+                )));
+            }
+            Some(mtu) => mtu,
+            None => match transport.mtu().await? {
+                Some(mtu) if mtu < DEFAULT_MTU => {
+                    warn!(
+                        "Link reports an MTU of {}, using {} instead",
+                        mtu, DEFAULT_MTU
+                    );
+                    DEFAULT_MTU
+                }
+                mtu => mtu.unwrap_or(DEFAULT_MTU),
+            },
+        };
+        debug!("Using MTU {}", mtu);
+
+        let notifications = if transport.supports_notifications() {
+            debug!("Subscribing to CCS notifications");
+            Some(transport.subscribe().await?)
+        } else {
+            debug!("CCS characteristic can't notify, polling instead");
+            None
+        };
+
+        let transport: Arc<dyn QuestTransport> = Arc::from(transport);
+        let (dispatcher, dispatcher_task) = Dispatcher::spawn(transport.clone(), notifications);
+
+        Ok(Arc::new(Self {
+            transport,
+            dispatcher,
+            mtu,
+            write_lock: Arc::new(tokio::sync::Mutex::new(())),
+            handshake: Mutex::new(HandshakeInfo::default()),
+            _dispatcher_task: dispatcher_task,
+        }))
+    }
+
+    async fn is_alive(&self) -> bool {
+        !self.dispatcher.is_closed() && self.transport.is_connected().await.unwrap_or(false)
+    }
+}
+
+impl QuestDevice {
+    pub fn transport(&self) -> Arc<dyn QuestTransport> {
+        self.current_session().transport.clone()
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.current_session().mtu
+    }
+
+    pub(crate) fn current_session(&self) -> Arc<Session> {
+        self.session.read().unwrap().clone()
+    }
+
+    // the current session, reconnecting first if its link dropped
+    pub(crate) async fn session(&self) -> Result<Arc<Session>, HzospalError> {
+        let session = self.current_session();
+        if session.is_alive().await {
+            return Ok(session);
+        }
+
+        if self.connector.is_none() || self.reconnect_policy.max_attempts == 0 {
+            return Err(HzospalError::NotConnected);
+        }
+
+        let _guard = self.reconnect_lock.lock().await;
+
+        // somebody else may have reconnected while we waited for the lock
+        let session = self.current_session();
+        if session.is_alive().await {
+            return Ok(session);
+        }
+
+        self.reestablish().await
+    }
+
+    // drops the current link and sets up a new session, even if the old one
+    // looked fine
+    pub async fn reconnect(&self) -> Result<(), HzospalError> {
+        let _guard = self.reconnect_lock.lock().await;
+
+        let _ = self.current_session().transport.disconnect().await;
+        self.reestablish().await?;
+
+        Ok(())
+    }
+
+    // one fresh link and handshake with the current device_key, no retries so
+    // a key the headset refuses shows up right away
+    pub(crate) async fn handshake_again(&self) -> Result<(), HzospalError> {
+        let connector = self.connector.as_ref().ok_or(HzospalError::NotConnected)?;
+        let _guard = self.reconnect_lock.lock().await;
+
+        let _ = self.current_session().transport.disconnect().await;
+        let session = self.open_session(connector).await?;
+
+        *self.session.write().unwrap() = session;
+        *self.health.lock().unwrap() = LinkHealth::default();
+
+        Ok(())
+    }
+
+    async fn reestablish(&self) -> Result<Arc<Session>, HzospalError> {
+        let connector = self.connector.as_ref().ok_or(HzospalError::NotConnected)?;
+        let policy = &self.reconnect_policy;
+        let mut attempt = 1;
+
+        loop {
+            debug!(
+                "Reconnecting to {} (attempt {}/{})",
+                self.name, attempt, policy.max_attempts
+            );
+
+            match self.open_session(connector).await {
+                Ok(session) => {
+                    *self.session.write().unwrap() = session.clone();
+                    *self.health.lock().unwrap() = LinkHealth::default();
+                    debug!("Reconnected to {}", self.name);
+                    return Ok(session);
+                }
+                Err(e) if attempt < policy.max_attempts => {
+                    let backoff = policy.backoff(attempt);
+                    warn!("Reconnect failed ({}), retrying in {:?}", e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn open_session(&self, connector: &Connector) -> Result<Arc<Session>, HzospalError> {
+        let transport = connector().await?;
+        let session = Session::open(transport, self.mtu_override).await?;
+
+        self.sequence_number.store(0, Ordering::SeqCst);
+
+        let hello = with_timeout(
+            self.timeouts.handshake,
+            "handshake",
+            say_hello(self, &session),
+        )
+        .await?;
+
+        // outside the handshake timeout, whoever supplies the PIN may take a while
+        if hello.info.device_needs_to_be_unlocked {
+            unlock(self, &session).await?;
+        }
+
+        with_timeout(self.timeouts.handshake, "handshake", async {
+            // the headset kept its claim, so it has to ask for authentication
+            let challenge = hello.challenge.ok_or_else(|| {
+                HzospalError::Protocol("Headset is no longer claimed, refusing to re-claim".into())
+            })?;
+            let key = self
+                .device_key
+                .ok_or_else(|| HzospalError::Crypto("Device key not set".into()))?;
+            authenticate_device(self, &session, &key, challenge).await
+        })
+        .await?;
+
+        Ok(session)
+    }
+}
@@ -0,0 +1,769 @@
+// plays the headset side of the CCS protocol so everything above the
+// transport can be exercised without a Quest or a Bluetooth adapter
+
+use crate::{
+    com::oculus::companion::server::{
+        AuthenticateRequest, DevModeRequest, ErrorDetails, HelloRequest, HelloResponse,
+        HelloSignedData, OculusSetUserSecretRequest, OtaEnabledRequest, PinUnlockRequest,
+        PinUnlockResponse, Request, Response, SkipNuxAndLoginRequest, SkipNuxAndLoginResponse,
+        WifiConnectRequest, WifiNetwork,
+    },
+    protocol::{decoder::PacketAssembler, encoder::fragment_message},
+    transport::{FragmentStream, QuestTransport},
+};
+
+pub use crate::com::oculus::companion::server::{
+    ControllerStatusResponse, DevModeResponse, ErrorCode, HmdCapabilitiesResponse,
+    HmdStatusResponse, HmdVersionResponse, Method, OtaEnabledResponse, ResponseCode,
+    WifiScanResponse, WifiStatusResponse,
+};
+use crate::error::HzospalError;
+use async_trait::async_trait;
+use crypto_box::{
+    PublicKey, SalsaBox, SecretKey,
+    aead::{Aead, AeadCore, OsRng},
+};
+use futures::channel::mpsc;
+use hmac::{Hmac, Mac};
+use log::*;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{DerSignature, SigningKey};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+use x509_cert::der::Encode;
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::Validity;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Debug)]
+pub struct SimulatedFailure {
+    pub code: ResponseCode,
+    pub error: ErrorCode,
+    pub debug_details: Option<String>,
+}
+
+impl SimulatedFailure {
+    pub fn new(code: ResponseCode, error: ErrorCode) -> Self {
+        Self {
+            code,
+            error,
+            debug_details: None,
+        }
+    }
+}
+
+// everything the simulated headset reports, tests can change any of it at
+// any time through Simulator::state
+pub struct SimulatorState {
+    pub user_secret: Option<[u8; 32]>,
+    // the PinUnlock accepts, device_needs_to_be_unlocked says whether it's
+    // needed right now
+    pub pin: Option<String>,
+    pub device_needs_to_be_unlocked: bool,
+    pub is_device_managed: bool,
+    pub hmd_status: HmdStatusResponse,
+    pub hmd_version: HmdVersionResponse,
+    pub hmd_capabilities: HmdCapabilitiesResponse,
+    // answered with UNSUPPORTED_METHOD, like an older build would
+    pub unsupported: HashSet<Method>,
+    pub dev_mode: DevModeResponse,
+    pub ota_enabled: OtaEnabledResponse,
+    pub wifi_status: WifiStatusResponse,
+    pub wifi_scan: WifiScanResponse,
+    pub controllers: ControllerStatusResponse,
+    pub connected: bool,
+    pub supports_notifications: bool,
+    pub mtu: usize,
+    // sign the HelloResponse with a key the certificate doesn't vouch for,
+    // like a man in the middle would have to
+    pub forge_hello_signature: bool,
+    // answer in plaintext after the handshake, like a downgrade attempt
+    pub plaintext_responses: bool,
+    // send every response twice, byte for byte
+    pub replay_responses: bool,
+    // keep responses back until release_responses, like a headset that's
+    // slow to answer
+    pub hold_responses: bool,
+    pub requests: Vec<Method>,
+    failures: HashMap<Method, VecDeque<SimulatedFailure>>,
+    identity: Identity,
+}
+
+impl Default for SimulatorState {
+    fn default() -> Self {
+        Self {
+            user_secret: None,
+            pin: None,
+            device_needs_to_be_unlocked: false,
+            is_device_managed: false,
+            hmd_status: HmdStatusResponse {
+                battery_level: Some(100),
+                nux_completed: Some(false),
+                developer_mode: Some(false),
+                provisioned_serial: Some(format!(
+                    "1WMHH{:09}",
+                    rand::random::<u32>() % 1_000_000_000
+                )),
+                device_id: Some(hex::encode(rand::random::<[u8; 8]>())),
+                ..Default::default()
+            },
+            hmd_version: HmdVersionResponse {
+                model: Some("Quest 3".into()),
+                version_incremental: Some("51154110129000520".into()),
+                ..Default::default()
+            },
+            hmd_capabilities: HmdCapabilitiesResponse {
+                chromecast_available: Some(true),
+                multi_user_available: Some(false),
+                ..Default::default()
+            },
+            unsupported: HashSet::new(),
+            dev_mode: DevModeResponse { status: Some(0) },
+            ota_enabled: OtaEnabledResponse {
+                enabled: Some(true),
+            },
+            wifi_status: WifiStatusResponse {
+                enabled: Some(true),
+                ..Default::default()
+            },
+            wifi_scan: WifiScanResponse::default(),
+            controllers: ControllerStatusResponse::default(),
+            connected: true,
+            supports_notifications: true,
+            mtu: 247,
+            forge_hello_signature: false,
+            plaintext_responses: false,
+            replay_responses: false,
+            hold_responses: false,
+            requests: Vec::new(),
+            failures: HashMap::new(),
+            identity: Identity::generate(),
+        }
+    }
+}
+
+// a throwaway root and the headset certificate it issued
+struct Identity {
+    root: Vec<u8>,
+    certificate: Vec<u8>,
+    // sent after certificate in the HelloResponse
+    intermediates: Vec<u8>,
+    key: SigningKey,
+}
+
+impl Identity {
+    fn generate() -> Self {
+        let validity = Validity::from_now(Duration::from_secs(60 * 60 * 24 * 365))
+            .expect("validity fits in a certificate");
+
+        let root_key = SigningKey::random(&mut OsRng);
+        let root_name = Name::from_str("CN=hzospal simulator root").expect("valid name");
+        let root = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(1u32),
+            validity,
+            root_name.clone(),
+            SubjectPublicKeyInfoOwned::from_key(*root_key.verifying_key()).expect("P-256 key"),
+            &root_key,
+        )
+        .and_then(|builder| builder.build::<DerSignature>())
+        .expect("simulator root certificate");
+
+        let (certificate, key) = issue_leaf(root_name, &root_key, 2, "CN=hzospal simulator");
+
+        Self {
+            root: root.to_der().expect("encodable certificate"),
+            certificate,
+            intermediates: Vec::new(),
+            key,
+        }
+    }
+
+    // a new key whose certificate our own leaf issued, a leaf isn't a CA so
+    // no client should take the chain
+    fn issued_by_leaf(&self) -> Self {
+        let issuer = Name::from_str("CN=hzospal simulator").expect("valid name");
+        let (certificate, key) = issue_leaf(issuer, &self.key, 3, "CN=hzospal simulator leaf");
+
+        Self {
+            root: self.root.clone(),
+            certificate,
+            intermediates: self.chain(),
+            key,
+        }
+    }
+
+    fn chain(&self) -> Vec<u8> {
+        [&self.certificate[..], &self.intermediates].concat()
+    }
+}
+
+fn issue_leaf(
+    issuer: Name,
+    issuer_key: &SigningKey,
+    serial: u32,
+    subject: &str,
+) -> (Vec<u8>, SigningKey) {
+    let validity = Validity::from_now(Duration::from_secs(60 * 60 * 24 * 365))
+        .expect("validity fits in a certificate");
+
+    let key = SigningKey::random(&mut OsRng);
+    let certificate = CertificateBuilder::new(
+        Profile::Leaf {
+            issuer,
+            enable_key_agreement: false,
+            enable_key_encipherment: false,
+        },
+        SerialNumber::from(serial),
+        validity,
+        Name::from_str(subject).expect("valid name"),
+        SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()).expect("P-256 key"),
+        issuer_key,
+    )
+    .and_then(|builder| builder.build::<DerSignature>())
+    .expect("simulator certificate");
+
+    (certificate.to_der().expect("encodable certificate"), key)
+}
+
+struct Session {
+    assembler: PacketAssembler,
+    secret_key: SecretKey,
+    crypto_box: Option<SalsaBox>,
+    challenge: Option<Vec<u8>>,
+    authenticated: bool,
+    outgoing: VecDeque<Vec<u8>>,
+    // sealed responses kept back by hold_responses
+    held: Vec<Vec<u8>>,
+    notifications: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    status_notifications: Option<mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            assembler: PacketAssembler::new(),
+            secret_key: SecretKey::generate(&mut OsRng),
+            crypto_box: None,
+            challenge: None,
+            authenticated: false,
+            outgoing: VecDeque::new(),
+            held: Vec::new(),
+            notifications: None,
+            status_notifications: None,
+        }
+    }
+}
+
+impl Session {
+    fn seal(&self, message: Vec<u8>) -> Result<Vec<u8>, HzospalError> {
+        let Some(crypto_box) = &self.crypto_box else {
+            return Ok(message);
+        };
+
+        let nonce = SalsaBox::generate_nonce(&mut OsRng);
+        let mut ciphertext = crypto_box
+            .encrypt(&nonce, &message[..])
+            .map_err(|_| HzospalError::Crypto("Simulator failed to encrypt response".into()))?;
+        let mut data = nonce.to_vec();
+        data.append(&mut ciphertext);
+        Ok(data)
+    }
+
+    fn send(&mut self, data: &[u8], mtu: usize) {
+        for packet in fragment_message(data, mtu) {
+            match &self.notifications {
+                Some(tx) => {
+                    let _ = tx.unbounded_send(packet);
+                }
+                None => self.outgoing.push_back(packet),
+            }
+        }
+    }
+}
+
+struct Inner {
+    state: SimulatorState,
+    session: Session,
+}
+
+impl Inner {
+    fn push_on_status(&self, data: &[u8]) {
+        let Some(tx) = &self.session.status_notifications else {
+            return;
+        };
+        for packet in fragment_message(data, self.state.mtu) {
+            let _ = tx.unbounded_send(packet);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Simulator {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new(SimulatorState::default())
+    }
+}
+
+impl Simulator {
+    pub fn new(state: SimulatorState) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                state,
+                session: Session::new(),
+            })),
+        }
+    }
+
+    // a headset that was already claimed with this key
+    pub fn claimed(user_secret: [u8; 32]) -> Self {
+        Self::new(SimulatorState {
+            user_secret: Some(user_secret),
+            ..Default::default()
+        })
+    }
+
+    // DER-encoded root that issued this headset's certificate
+    pub fn trust_anchor(&self) -> Vec<u8> {
+        self.state(|s| s.identity.root.clone())
+    }
+
+    // DER-encoded certificate presented in the HelloResponse
+    pub fn certificate(&self) -> Vec<u8> {
+        self.state(|s| s.identity.certificate.clone())
+    }
+
+    // new root, certificate and key, as if the headset was swapped out
+    pub fn rotate_certificate(&self) {
+        self.state(|s| s.identity = Identity::generate());
+    }
+
+    // new key and certificate, issued by the current certificate as if it
+    // were a CA, the chain still ends at trust_anchor
+    pub fn issue_from_leaf(&self) {
+        self.state(|s| s.identity = s.identity.issued_by_leaf());
+    }
+
+    pub fn transport(&self) -> SimulatorTransport {
+        SimulatorTransport {
+            simulator: self.clone(),
+        }
+    }
+
+    pub fn state<R>(&self, f: impl FnOnce(&mut SimulatorState) -> R) -> R {
+        f(&mut self.inner.lock().unwrap().state)
+    }
+
+    // answer the next request for method with this failure instead of handling it
+    pub fn fail_next(&self, method: Method, failure: SimulatedFailure) {
+        self.state(|s| s.failures.entry(method).or_default().push_back(failure));
+    }
+
+    // drop the current session as if the headset rebooted
+    pub fn reset_session(&self) {
+        self.inner.lock().unwrap().session = Session::new();
+    }
+
+    // drop the link as if the headset went out of range, it stays unreachable
+    // until connected is set again
+    pub fn drop_link(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.connected = false;
+        inner.session = Session::new();
+    }
+
+    // push the current hmd_status on the status characteristic
+    pub fn push_status(&self) -> Result<(), HzospalError> {
+        let body = self.state(|s| s.hmd_status.encode_to_vec());
+        self.push_status_body(body)
+    }
+
+    // push body framed and sealed the way hmd_status is, whatever it holds
+    pub fn push_status_body(&self, body: Vec<u8>) -> Result<(), HzospalError> {
+        let inner = self.inner.lock().unwrap();
+        let response = Response {
+            seq: None,
+            code: Some(ResponseCode::Success.into()),
+            body: Some(body),
+        };
+        let data = inner.session.seal(response.encode_to_vec())?;
+        inner.push_on_status(&data);
+        Ok(())
+    }
+
+    // send the responses hold_responses kept back, in the order the requests
+    // came in or the other way round
+    pub fn release_responses(&self, newest_first: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { state, session } = &mut *inner;
+
+        let mut held = std::mem::take(&mut session.held);
+        if newest_first {
+            held.reverse();
+        }
+        for data in held {
+            session.send(&data, state.mtu);
+        }
+    }
+
+    // push bytes the client can't be expected to understand
+    pub fn push_raw_status(&self, data: &[u8]) {
+        self.inner.lock().unwrap().push_on_status(data);
+    }
+
+    fn receive_fragment(&self, fragment: &[u8]) -> Result<(), HzospalError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { state, session } = &mut *inner;
+
+        if !state.connected {
+            return Err(HzospalError::NotConnected);
+        }
+
+        if fragment.len() > state.mtu.saturating_sub(3) {
+            return Err(HzospalError::transport(format!(
+                "Fragment of {} bytes does not fit the simulated MTU of {}",
+                fragment.len(),
+                state.mtu
+            )));
+        }
+        // what BlueZ refuses whatever the MTU
+        if fragment.len() > 512 {
+            return Err(HzospalError::transport(format!(
+                "Fragment of {} bytes is longer than an attribute value can be",
+                fragment.len()
+            )));
+        }
+
+        let Some(message) = session.assembler.handle_notification(fragment) else {
+            return Ok(());
+        };
+
+        let decrypted = session.crypto_box.as_ref().and_then(|crypto_box| {
+            if message.len() < 24 {
+                return None;
+            }
+            let (nonce_bytes, ciphertext) = message.split_at(24);
+            let nonce = crypto_box::aead::Nonce::<SalsaBox>::from_slice(nonce_bytes);
+            crypto_box.decrypt(nonce, ciphertext).ok()
+        });
+
+        // a new hello is always plaintext, even in the middle of a session
+        let request = match decrypted {
+            Some(bytes) => Request::decode(&*bytes)?,
+            None => {
+                let request = Request::decode(&*message)?;
+                if session.crypto_box.is_some() && request.method() != Method::Hello {
+                    return Err(HzospalError::Crypto(
+                        "Simulator failed to decrypt request".into(),
+                    ));
+                }
+                request
+            }
+        };
+        let method = Method::try_from(request.method.unwrap_or_default())?;
+        let body = request.body.unwrap_or_default();
+
+        debug!("Simulator received {:?}", method);
+        state.requests.push(method);
+
+        let (code, response_body) =
+            match state.failures.get_mut(&method).and_then(|f| f.pop_front()) {
+                Some(failure) => failure_response(failure),
+                None => handle_request(state, session, method, &body)?,
+            };
+
+        let response = Response {
+            seq: request.seq,
+            code: Some(code.into()),
+            body: Some(response_body),
+        };
+
+        let mut response_bytes = Vec::new();
+        response.encode(&mut response_bytes)?;
+
+        // the hello response itself is the last plaintext message
+        let data = match method {
+            Method::Hello => response_bytes,
+            _ if state.plaintext_responses => response_bytes,
+            _ => session.seal(response_bytes)?,
+        };
+
+        if state.hold_responses && method != Method::Hello {
+            session.held.push(data);
+            return Ok(());
+        }
+
+        let copies = if state.replay_responses { 2 } else { 1 };
+        for _ in 0..copies {
+            session.send(&data, state.mtu);
+        }
+
+        Ok(())
+    }
+}
+
+fn failure_response(failure: SimulatedFailure) -> (ResponseCode, Vec<u8>) {
+    let details = ErrorDetails {
+        code: Some(failure.error.into()),
+        debug_details: failure.debug_details,
+        ..Default::default()
+    };
+    (failure.code, details.encode_to_vec())
+}
+
+fn error_response(error: ErrorCode) -> (ResponseCode, Vec<u8>) {
+    failure_response(SimulatedFailure::new(ResponseCode::Fail, error))
+}
+
+fn handle_request(
+    state: &mut SimulatorState,
+    session: &mut Session,
+    method: Method,
+    body: &[u8],
+) -> Result<(ResponseCode, Vec<u8>), HzospalError> {
+    let ok = |body: Vec<u8>| Ok((ResponseCode::Success, body));
+
+    match method {
+        Method::Hello => {
+            let hello = HelloRequest::decode(body)?;
+            let client_public_key: [u8; 32] = hello
+                .client_public_key
+                .ok_or_else(|| {
+                    HzospalError::Protocol("HelloRequest was missing client public key".into())
+                })?
+                .try_into()
+                .map_err(|_| HzospalError::Protocol("Client public key is not 32 bytes".into()))?;
+
+            let challenge = state.user_secret.map(|_| {
+                let mut challenge = vec![0u8; 32];
+                rand::fill(&mut challenge[..]);
+                challenge
+            });
+
+            let signed_data = HelloSignedData {
+                server_public_key: Some(session.secret_key.public_key().as_bytes().to_vec()),
+                authentication_challenge: challenge.clone(),
+                device_needs_to_be_unlocked: Some(state.device_needs_to_be_unlocked),
+                is_device_managed: Some(state.is_device_managed),
+            };
+
+            session.crypto_box = Some(SalsaBox::new(
+                &PublicKey::from(client_public_key),
+                &session.secret_key,
+            ));
+            session.challenge = challenge;
+            session.authenticated = false;
+
+            let signed_data = signed_data.encode_to_vec();
+
+            let signature: DerSignature = if state.forge_hello_signature {
+                SigningKey::random(&mut OsRng).sign(&signed_data)
+            } else {
+                state.identity.key.sign(&signed_data)
+            };
+
+            // a client that already knows our certificate doesn't get it again
+            let fingerprint: [u8; 32] = Sha256::digest(&state.identity.certificate).into();
+            let server_certificate = match hello.known_cert_fingerprint {
+                Some(known) if known == fingerprint => None,
+                _ => Some(state.identity.chain()),
+            };
+
+            let hello_resp = HelloResponse {
+                signed_data: Some(signed_data),
+                signature: Some(signature.as_bytes().to_vec()),
+                server_certificate,
+            };
+            ok(hello_resp.encode_to_vec())
+        }
+        Method::PinUnlock => {
+            let req = PinUnlockRequest::decode(body)?;
+            let correct = state.pin.is_some() && req.pin == state.pin;
+            if correct {
+                state.device_needs_to_be_unlocked = false;
+            }
+            ok(PinUnlockResponse {
+                correct: Some(correct),
+                cooldown_timeout: Some(0),
+            }
+            .encode_to_vec())
+        }
+        _ if state.device_needs_to_be_unlocked => Ok(error_response(ErrorCode::UserPinRequired)),
+        Method::Authenticate => {
+            let (Some(key), Some(challenge)) = (state.user_secret, &session.challenge) else {
+                return Ok(error_response(ErrorCode::AuthenticationFailure));
+            };
+            let auth = AuthenticateRequest::decode(body)?;
+
+            let mut hmac = HmacSha256::new_from_slice(&key)
+                .map_err(|e| HzospalError::Crypto(e.to_string()))?;
+            hmac.update(challenge);
+            let valid = hmac
+                .verify_slice(&auth.signed_authentication_challenge.unwrap_or_default())
+                .is_ok();
+
+            if !valid {
+                return Ok(error_response(ErrorCode::AuthenticationFailure));
+            }
+
+            session.authenticated = true;
+            ok(Vec::new())
+        }
+        Method::OculusSetUserSecret if state.user_secret.is_none() => {
+            let claim = OculusSetUserSecretRequest::decode(body)?;
+            let key: [u8; 32] = match claim.user_secret_key.unwrap_or_default().try_into() {
+                Ok(key) => key,
+                Err(_) => return Ok(error_response(ErrorCode::BadArguement)),
+            };
+
+            state.user_secret = Some(key);
+            session.authenticated = true;
+            ok(Vec::new())
+        }
+        _ if !session.authenticated => Ok(error_response(ErrorCode::AuthenticationFailure)),
+        // the owner can change the secret without a wipe
+        Method::OculusSetUserSecret => {
+            let rekey = OculusSetUserSecretRequest::decode(body)?;
+            let key: [u8; 32] = match rekey.user_secret_key.unwrap_or_default().try_into() {
+                Ok(key) => key,
+                Err(_) => return Ok(error_response(ErrorCode::BadArguement)),
+            };
+
+            state.user_secret = Some(key);
+            ok(Vec::new())
+        }
+        _ if state.unsupported.contains(&method) => {
+            Ok(error_response(ErrorCode::UnsupportedMethod))
+        }
+        Method::Ping => ok(Vec::new()),
+        Method::HmdStatus => ok(state.hmd_status.encode_to_vec()),
+        Method::HmdVersion => ok(state.hmd_version.encode_to_vec()),
+        Method::HmdCapabilities => ok(state.hmd_capabilities.encode_to_vec()),
+        Method::DevModeSet => {
+            let req = DevModeRequest::decode(body)?;
+            let mode = req.mode.unwrap_or_default();
+            state.dev_mode.status = Some(mode);
+            state.hmd_status.developer_mode = Some(mode != 0);
+            ok(Vec::new())
+        }
+        Method::DevModeStatus => ok(state.dev_mode.encode_to_vec()),
+        Method::OtaEnabledSet => {
+            let req = OtaEnabledRequest::decode(body)?;
+            state.ota_enabled.enabled = req.enable;
+            ok(Vec::new())
+        }
+        Method::OtaEnabledStatus => ok(state.ota_enabled.encode_to_vec()),
+        Method::WifiConnect => {
+            let req = WifiConnectRequest::decode(body)?;
+            let network = WifiNetwork {
+                ssid: req.ssid,
+                auth: req.auth.into_iter().collect(),
+                ..Default::default()
+            };
+            state.wifi_status.network = Some(network);
+            state.hmd_status.wifi_configured = Some(true);
+            state.hmd_status.wifi_connected = Some(true);
+            ok(Vec::new())
+        }
+        Method::WifiStatus => ok(state.wifi_status.encode_to_vec()),
+        Method::WifiScan => ok(state.wifi_scan.encode_to_vec()),
+        Method::ControllerStatus => ok(state.controllers.encode_to_vec()),
+        Method::MetaSetAccessTokenCombined => {
+            state.hmd_status.horizon_logged_in_meta = Some(true);
+            ok(Vec::new())
+        }
+        Method::RetailSkipFirstTimeNux => {
+            let req = SkipNuxAndLoginRequest::decode(body)?;
+            if req.get_status == Some(true) {
+                let resp = SkipNuxAndLoginResponse { status: Some(0) };
+                return ok(resp.encode_to_vec());
+            }
+            state.hmd_status.nux_completed = Some(true);
+            ok(Vec::new())
+        }
+        _ => Ok(error_response(ErrorCode::UnsupportedMethod)),
+    }
+}
+
+pub struct SimulatorTransport {
+    simulator: Simulator,
+}
+
+#[async_trait]
+impl QuestTransport for SimulatorTransport {
+    async fn write_fragment(&self, fragment: &[u8]) -> Result<(), HzospalError> {
+        self.simulator.receive_fragment(fragment)
+    }
+
+    async fn read_fragment(&self) -> Result<Vec<u8>, HzospalError> {
+        let mut inner = self.simulator.inner.lock().unwrap();
+        if !inner.state.connected {
+            return Err(HzospalError::NotConnected);
+        }
+
+        // same as the headset, 0xFF means nothing to read yet
+        Ok(inner
+            .session
+            .outgoing
+            .pop_front()
+            .unwrap_or_else(|| vec![0xFF]))
+    }
+
+    fn supports_notifications(&self) -> bool {
+        self.simulator.state(|s| s.supports_notifications)
+    }
+
+    async fn subscribe(&self) -> Result<FragmentStream, HzospalError> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut inner = self.simulator.inner.lock().unwrap();
+        if !inner.state.supports_notifications {
+            return Err(HzospalError::transport(
+                "Simulated characteristic does not support notifications",
+            ));
+        }
+
+        for packet in inner.session.outgoing.drain(..) {
+            let _ = tx.unbounded_send(packet);
+        }
+        inner.session.notifications = Some(tx);
+
+        Ok(Box::pin(rx))
+    }
+
+    async fn subscribe_status(&self) -> Result<FragmentStream, HzospalError> {
+        let (tx, rx) = mpsc::unbounded();
+        self.simulator
+            .inner
+            .lock()
+            .unwrap()
+            .session
+            .status_notifications = Some(tx);
+        Ok(Box::pin(rx))
+    }
+
+    async fn is_connected(&self) -> Result<bool, HzospalError> {
+        Ok(self.simulator.inner.lock().unwrap().state.connected)
+    }
+
+    async fn disconnect(&self) -> Result<(), HzospalError> {
+        self.simulator.state(|s| s.connected = false);
+        self.simulator.reset_session();
+        Ok(())
+    }
+
+    async fn mtu(&self) -> Result<Option<usize>, HzospalError> {
+        Ok(Some(self.simulator.state(|s| s.mtu)))
+    }
+}
@@ -0,0 +1,55 @@
+use crate::error::HzospalError;
+use crate::{
+    QuestDevice,
+    protocol::{decoder::PacketAssembler, dispatcher::Dispatcher},
+};
+use futures::stream::{Stream, StreamExt};
+use log::*;
+use prost::Message;
+use std::pin::Pin;
+
+pub use crate::com::oculus::companion::server::HmdStatusResponse;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatusEvent {
+    HmdStatus(HmdStatusResponse),
+    // anything we couldn't make sense of, as reassembled off the characteristic
+    Raw(Vec<u8>),
+}
+
+pub type StatusStream = Pin<Box<dyn Stream<Item = StatusEvent> + Send>>;
+
+// whatever the headset pushes on its status characteristic, the stream ends
+// when the link drops, call this again once reconnected
+pub async fn status_events(quest: &QuestDevice) -> Result<StatusStream, HzospalError> {
+    let session = quest.session().await?;
+    let fragments = session.transport.subscribe_status().await?;
+
+    let dispatcher = session.dispatcher.clone();
+    let mut assembler = PacketAssembler::new();
+
+    Ok(Box::pin(fragments.filter_map(move |fragment| {
+        let event = assembler
+            .handle_notification(&fragment)
+            .map(|message| decode_status(&dispatcher, message));
+        async move { event }
+    })))
+}
+
+// the push format is assumed, nothing documents it: a Response sealed like
+// any answer, with an HmdStatusResponse body. protobuf makes something out of
+// most bytes, so a body without a field we know, or with one we don't, stays
+// Raw
+fn decode_status(dispatcher: &Dispatcher, message: Vec<u8>) -> StatusEvent {
+    if let Ok(response) = dispatcher.unseal(&message)
+        && let Some(body) = &response.body
+        && let Ok(status) = HmdStatusResponse::decode(&body[..])
+        && status != HmdStatusResponse::default()
+        && status.encoded_len() == body.len()
+    {
+        return StatusEvent::HmdStatus(status);
+    }
+
+    debug!("Undecodable status push: {}", hex::encode(&message));
+    StatusEvent::Raw(message)
+}
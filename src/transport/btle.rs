@@ -0,0 +1,181 @@
+use crate::error::HzospalError;
+use crate::transport::{FragmentStream, QuestTransport};
+use crate::{Timeouts, with_timeout};
+use async_trait::async_trait;
+use btleplug::api::{
+    Central as _, CentralEvent, CharPropFlags, Characteristic, Peripheral as _, WriteType,
+};
+use btleplug::platform::{Adapter, Peripheral};
+use futures::stream::StreamExt;
+use log::*;
+use uuid::{Uuid, uuid};
+
+pub const CCS_UUID: Uuid = uuid!("7a442881-509c-47fa-ac02-b06a37d9eb76");
+pub const STATUS_UUID: Uuid = uuid!("7a442666-509c-47fa-ac02-b06a37d9eb76");
+
+pub struct BtleTransport {
+    // needed to hear about disconnects, btleplug reports them per adapter
+    pub adapter: Adapter,
+    pub peripheral: Peripheral,
+    pub ccs_characteristic: Characteristic,
+    pub status_characteristic: Characteristic,
+}
+
+impl BtleTransport {
+    // connects if needed, then finds the CCS and status characteristics
+    pub async fn connect(
+        adapter: Adapter,
+        peripheral: Peripheral,
+        timeouts: &Timeouts,
+    ) -> Result<Self, HzospalError> {
+        if !peripheral.is_connected().await? {
+            debug!("Connecting to {}...", peripheral.address());
+            with_timeout(timeouts.connect, "connecting", async {
+                Ok(peripheral.connect().await?)
+            })
+            .await?;
+            debug!("Connected.");
+        }
+
+        with_timeout(timeouts.service_discovery, "discovering services", async {
+            Ok(peripheral.discover_services().await?)
+        })
+        .await?;
+        let characteristics = peripheral.characteristics();
+
+        let ccs_characteristic = characteristics
+            .iter()
+            .find(|c| c.uuid == CCS_UUID)
+            .cloned()
+            .ok_or_else(|| HzospalError::Protocol("Failed to find CCS characteristic".into()))?;
+
+        let status_characteristic = characteristics
+            .iter()
+            .find(|c| c.uuid == STATUS_UUID)
+            .cloned()
+            .ok_or_else(|| HzospalError::Protocol("Failed to find status characteristic".into()))?;
+
+        Ok(Self {
+            adapter,
+            peripheral,
+            ccs_characteristic,
+            status_characteristic,
+        })
+    }
+
+    // notifications() carries every characteristic, keep only this one's
+    async fn subscribe_to(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<FragmentStream, HzospalError> {
+        self.peripheral.subscribe(characteristic).await?;
+
+        let uuid = characteristic.uuid;
+        let notifications = self.peripheral.notifications().await?;
+
+        Ok(Box::pin(notifications.filter_map(move |n| async move {
+            (n.uuid == uuid).then_some(n.value)
+        })))
+    }
+}
+
+#[async_trait]
+impl QuestTransport for BtleTransport {
+    async fn write_fragment(&self, fragment: &[u8]) -> Result<(), HzospalError> {
+        self.peripheral
+            .write(&self.ccs_characteristic, fragment, WriteType::WithResponse)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_fragment(&self) -> Result<Vec<u8>, HzospalError> {
+        Ok(self.peripheral.read(&self.ccs_characteristic).await?)
+    }
+
+    fn supports_notifications(&self) -> bool {
+        self.ccs_characteristic
+            .properties
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+    }
+
+    async fn subscribe(&self) -> Result<FragmentStream, HzospalError> {
+        self.subscribe_to(&self.ccs_characteristic).await
+    }
+
+    async fn subscribe_status(&self) -> Result<FragmentStream, HzospalError> {
+        self.subscribe_to(&self.status_characteristic).await
+    }
+
+    async fn is_connected(&self) -> Result<bool, HzospalError> {
+        Ok(self.peripheral.is_connected().await?)
+    }
+
+    async fn disconnect(&self) -> Result<(), HzospalError> {
+        Ok(self.peripheral.disconnect().await?)
+    }
+
+    async fn disconnected(&self) -> Result<(), HzospalError> {
+        let id = self.peripheral.id();
+        let mut events = self.adapter.events().await?;
+
+        // it may have dropped before we started listening
+        if !self.peripheral.is_connected().await? {
+            return Ok(());
+        }
+
+        while let Some(event) = events.next().await {
+            if let CentralEvent::DeviceDisconnected(disconnected) = event
+                && disconnected == id
+            {
+                debug!("{} disconnected", self.peripheral.address());
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn mtu(&self) -> Result<Option<usize>, HzospalError> {
+        bluez_mtu(self).await
+    }
+}
+
+// btleplug doesn't expose the ATT MTU, but BlueZ negotiates it on connect and
+// reports it on the characteristic, so ask it directly
+#[cfg(target_os = "linux")]
+async fn bluez_mtu(transport: &BtleTransport) -> Result<Option<usize>, HzospalError> {
+    let (resource, session) = bluez_async::BluetoothSession::new().await?;
+    let resource = tokio::spawn(resource);
+
+    let address = transport.peripheral.address().to_string();
+    let mut mtu = None;
+
+    for device in session.get_devices().await? {
+        if !device
+            .mac_address
+            .to_string()
+            .eq_ignore_ascii_case(&address)
+        {
+            continue;
+        }
+
+        for service in session.get_services(&device.id).await? {
+            if let Ok(characteristic) = session
+                .get_characteristic_by_uuid(&service.id, transport.ccs_characteristic.uuid)
+                .await
+            {
+                mtu = characteristic.mtu.map(usize::from);
+                break;
+            }
+        }
+    }
+
+    resource.abort();
+
+    Ok(mtu)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn bluez_mtu(_transport: &BtleTransport) -> Result<Option<usize>, HzospalError> {
+    Ok(None)
+}
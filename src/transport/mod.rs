@@ -0,0 +1,50 @@
+mod btle;
+
+pub use btle::BtleTransport;
+
+use crate::error::HzospalError;
+use async_trait::async_trait;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::time::Duration;
+
+pub type FragmentStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+// everything the CCS protocol needs from the link, fragments are the raw
+// ATT-sized chunks produced by fragment_message, not whole messages
+#[async_trait]
+pub trait QuestTransport: Send + Sync {
+    async fn write_fragment(&self, fragment: &[u8]) -> Result<(), HzospalError>;
+
+    async fn read_fragment(&self) -> Result<Vec<u8>, HzospalError>;
+
+    // whether subscribe can be used instead of polling read_fragment
+    fn supports_notifications(&self) -> bool;
+
+    async fn subscribe(&self) -> Result<FragmentStream, HzospalError>;
+
+    // pushes from the headset on the status characteristic, same framing as
+    // the CCS characteristic
+    async fn subscribe_status(&self) -> Result<FragmentStream, HzospalError> {
+        Err(HzospalError::transport(
+            "Transport has no status characteristic",
+        ))
+    }
+
+    async fn is_connected(&self) -> Result<bool, HzospalError>;
+
+    async fn disconnect(&self) -> Result<(), HzospalError>;
+
+    // resolves once the link drops, links that can't report it get polled
+    async fn disconnected(&self) -> Result<(), HzospalError> {
+        while self.is_connected().await? {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Ok(())
+    }
+
+    // negotiated ATT MTU, None if the link can't tell us
+    async fn mtu(&self) -> Result<Option<usize>, HzospalError> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{connect, connect_options, connect_reconnecting, options};
+use hzospal::ConnectionOptions;
+use hzospal::access::{MAX_PIN_ATTEMPTS, PinAttempt, PinPrompt};
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::{get_hmd_status, rekey, set_dev_mode};
+use hzospal::simulator::Simulator;
+use std::sync::{Arc, Mutex};
+
+fn locked() -> Simulator {
+    let sim = Simulator::default();
+    sim.state(|s| {
+        s.pin = Some("1234".into());
+        s.device_needs_to_be_unlocked = true;
+    });
+    sim
+}
+
+fn with_prompt(sim: &Simulator, prompt: PinPrompt) -> ConnectionOptions {
+    ConnectionOptions {
+        pin_prompt: Some(prompt),
+        ..options(sim)
+    }
+}
+
+#[tokio::test]
+async fn locked_headsets_need_a_prompt() {
+    let sim = locked();
+
+    let e = connect(&sim, None).await.err().unwrap();
+    assert!(matches!(e, HzospalError::Locked(_)), "{e}");
+    assert_eq!(sim.state(|s| s.user_secret), None);
+}
+
+#[tokio::test]
+async fn stops_asking_after_max_attempts() {
+    let sim = locked();
+    let asked = Arc::new(Mutex::new(0));
+    let count = asked.clone();
+    let prompt = PinPrompt::new(move |_| {
+        *count.lock().unwrap() += 1;
+        Box::pin(async { Some("0000".to_string()) })
+    });
+
+    let e = connect_options(&sim, None, with_prompt(&sim, prompt))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(e, HzospalError::Locked(_)), "{e}");
+    assert_eq!(*asked.lock().unwrap(), MAX_PIN_ATTEMPTS);
+    assert!(sim.state(|s| s.device_needs_to_be_unlocked));
+}
+
+#[tokio::test]
+async fn unlocks_with_the_right_pin() {
+    let sim = locked();
+    let attempts = Arc::new(Mutex::new(Vec::new()));
+    let seen = attempts.clone();
+    let prompt = PinPrompt::new(move |attempt: PinAttempt| {
+        seen.lock().unwrap().push(attempt.attempt);
+        let pin = if attempt.attempt == 1 { "9" } else { "1234" };
+        Box::pin(async move { Some(pin.to_string()) })
+    });
+
+    let quest = connect_options(&sim, None, with_prompt(&sim, prompt))
+        .await
+        .unwrap();
+    assert_eq!(*attempts.lock().unwrap(), vec![1, 2]);
+    assert!(!quest.handshake().device_needs_to_be_unlocked);
+    assert!(sim.state(|s| s.user_secret.is_some()));
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn claims_an_unclaimed_managed_headset() {
+    let sim = Simulator::default();
+    sim.state(|s| s.is_device_managed = true);
+
+    let quest = connect(&sim, None).await.unwrap();
+    assert!(quest.handshake().is_device_managed);
+    assert_eq!(sim.state(|s| s.user_secret), quest.device_key);
+}
+
+#[tokio::test]
+async fn refuses_restricted_methods_on_managed_headsets() {
+    let sim = Simulator::default();
+    sim.state(|s| s.is_device_managed = true);
+    let mut quest = connect_reconnecting(&sim, None, options(&sim))
+        .await
+        .unwrap();
+    let key = quest.device_key;
+
+    let sent = sim.state(|s| s.requests.len());
+    let e = set_dev_mode(&quest, true).await.unwrap_err();
+    assert!(matches!(e, HzospalError::Managed(_)), "{e}");
+    let e = rekey(&mut quest, [7; 32]).await.unwrap_err();
+    assert!(matches!(e, HzospalError::Managed(_)), "{e}");
+    assert_eq!(sim.state(|s| s.requests.len()), sent);
+    assert_eq!(sim.state(|s| s.user_secret), key);
+
+    get_hmd_status(&quest).await.unwrap();
+}
@@ -0,0 +1,49 @@
+use btleplug::api::BDAddr;
+use hzospal::adapter::{AdapterInfo, AdapterSelector};
+
+fn adapter(index: usize, info: &str, address: Option<&str>) -> AdapterInfo {
+    AdapterInfo {
+        index,
+        info: info.into(),
+        address: address.map(|a| a.parse().unwrap()),
+    }
+}
+
+#[test]
+fn parses_selectors() {
+    assert!(matches!("all".parse(), Ok(AdapterSelector::All)));
+    assert!(matches!("ALL".parse(), Ok(AdapterSelector::All)));
+    assert!(matches!("1".parse(), Ok(AdapterSelector::Index(1))));
+    assert!(matches!(
+        "00:1A:7D:DA:71:13".parse(),
+        Ok(AdapterSelector::Address(a)) if a == "00:1A:7D:DA:71:13".parse::<BDAddr>().unwrap()
+    ));
+    assert!(matches!(
+        "hci*".parse(),
+        Ok(AdapterSelector::Name(n)) if n == "hci*"
+    ));
+}
+
+#[test]
+fn matches_adapters() {
+    let first = adapter(0, "hci0 (usb:v1D6Bp0246d0540)", Some("00:1A:7D:DA:71:13"));
+    let second = adapter(1, "hci1 (usb:v0A12p0001d8891)", None);
+
+    assert!(AdapterSelector::First.matches(&first));
+    assert!(!AdapterSelector::First.matches(&second));
+    assert!(AdapterSelector::Index(1).matches(&second));
+
+    let address: AdapterSelector = "00:1a:7d:da:71:13".parse().unwrap();
+    assert!(address.matches(&first));
+    assert!(!address.matches(&second));
+
+    let name: AdapterSelector = "HCI1".parse().unwrap();
+    assert!(name.matches(&second));
+    assert!(!name.matches(&first));
+
+    let info: AdapterSelector = "*v0A12*".parse().unwrap();
+    assert!(info.matches(&second));
+
+    assert!(AdapterSelector::All.matches(&first));
+    assert!(AdapterSelector::All.matches(&second));
+}
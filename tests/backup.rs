@@ -0,0 +1,109 @@
+mod common;
+
+use common::connect_options;
+use hzospal::ConnectionOptions;
+use hzospal::error::HzospalError;
+use hzospal::keystore::{HeadsetIdentity, KeyEntry, KeyFormat, KeyStore, import_key};
+use hzospal::protocol::certificate::CertificateVerification;
+use hzospal::simulator::Simulator;
+
+fn entry() -> KeyEntry {
+    KeyEntry {
+        nickname: Some("living room".into()),
+        identity: HeadsetIdentity {
+            address: Some("2C:26:17:00:00:01".into()),
+            device_id: Some("0123456789abcdef".into()),
+            provisioned_serial: Some("1WMHH000000001".into()),
+            certificate_fingerprint: Some([7; 32]),
+        },
+        user_secret: [42; 32],
+        pending_secret: None,
+        certificate: None,
+        claimed_at: 1_700_000_000,
+    }
+}
+
+#[test]
+fn armored_keeps_who_the_key_belongs_to() {
+    let entry = entry();
+    let text = entry.export(KeyFormat::Armored).unwrap();
+
+    let imported = import_key(&text).unwrap();
+    assert_eq!(imported, entry);
+}
+
+#[test]
+fn hex_and_base64_only_carry_the_key() {
+    let entry = entry();
+
+    for format in [KeyFormat::Hex, KeyFormat::Base64] {
+        let imported = import_key(&entry.export(format).unwrap()).unwrap();
+        assert_eq!(imported.user_secret, entry.user_secret);
+        assert_eq!(imported.identity, HeadsetIdentity::default());
+    }
+}
+
+#[test]
+fn catches_typos_in_armored_keys() {
+    let text = entry().export(KeyFormat::Armored).unwrap();
+
+    let typo = text.replace("living room", "living rooms");
+    assert!(import_key(&typo).is_err());
+    assert!(import_key("not a key").is_err());
+}
+
+#[test]
+fn draws_a_qr_code() {
+    let qr = entry().export(KeyFormat::Qr).unwrap();
+
+    assert!(qr.lines().count() > 10);
+}
+
+#[test]
+fn parses_format_names() {
+    assert_eq!("HEX".parse(), Ok(KeyFormat::Hex));
+    assert_eq!("armor".parse(), Ok(KeyFormat::Armored));
+    assert!("pem".parse::<KeyFormat>().is_err());
+}
+
+#[tokio::test]
+async fn an_imported_key_pins_the_certificate_fingerprint() {
+    let sim = Simulator::default();
+    let insecure = |pinned_fingerprint| ConnectionOptions {
+        certificate_verification: CertificateVerification::Insecure,
+        pinned_fingerprint,
+        ..Default::default()
+    };
+    let quest = connect_options(&sim, None, insecure(None)).await.unwrap();
+    let mut store = KeyStore::default();
+    let text = store
+        .remember(&quest, None)
+        .unwrap()
+        .export(KeyFormat::Armored)
+        .unwrap();
+
+    // the armored block only carries the fingerprint
+    let imported = import_key(&text).unwrap();
+    assert_eq!(imported.certificate, None);
+    let pinned = imported.identity.certificate_fingerprint;
+    assert!(pinned.is_some());
+
+    // any other certificate is refused, --insecure or not
+    let impostor = Simulator::claimed(imported.user_secret);
+    let result = connect_options(&impostor, Some(imported.user_secret), insecure(pinned)).await;
+    assert!(matches!(result, Err(HzospalError::Certificate(_))));
+
+    // so is the right certificate signed with another key
+    sim.reset_session();
+    sim.state(|s| s.forge_hello_signature = true);
+    let result = connect_options(&sim, Some(imported.user_secret), insecure(pinned)).await;
+    assert!(matches!(result, Err(HzospalError::Certificate(_))));
+
+    // the headset itself gets through, and the certificate is pinned from then on
+    sim.reset_session();
+    sim.state(|s| s.forge_hello_signature = false);
+    let quest = connect_options(&sim, Some(imported.user_secret), insecure(pinned))
+        .await
+        .unwrap();
+    assert_eq!(quest.server_certificate, Some(sim.certificate()));
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use common::connect;
+use hzospal::error::{ErrorCode, HzospalError};
+use hzospal::simulator::{Method, Simulator};
+use serde_json::json;
+
+#[tokio::test]
+async fn sends_and_answers_json() {
+    let sim = Simulator::default();
+    sim.state(|s| s.hmd_status.battery_level = Some(55));
+    let quest = connect(&sim, None).await.unwrap();
+
+    let status = quest.call_json(Method::HmdStatus, None).await.unwrap();
+    assert_eq!(status["battery_level"], json!(55));
+
+    let set = quest
+        .call_json(Method::DevModeSet, Some(json!({"mode": 1})))
+        .await
+        .unwrap();
+    assert_eq!(set, json!(null));
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+
+    // {} for a method without a request body
+    let dev = quest
+        .call_json(Method::DevModeStatus, Some(json!({})))
+        .await
+        .unwrap();
+    assert_eq!(dev, json!({"status": 1}));
+}
+
+#[tokio::test]
+async fn refuses_bad_requests_before_sending() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let sent = sim.state(|s| s.requests.len());
+
+    let e = quest
+        .call_json(Method::DevModeSet, Some(json!({"mode": "on"})))
+        .await
+        .unwrap_err();
+    assert!(matches!(e, HzospalError::BadRequest(_)), "{e}");
+
+    let e = quest.call_json(Method::Unknown, None).await.unwrap_err();
+    assert!(matches!(e, HzospalError::BadRequest(_)), "{e}");
+
+    assert_eq!(sim.state(|s| s.requests.len()), sent);
+}
+
+#[tokio::test]
+async fn device_errors_come_back_as_errors() {
+    let sim = Simulator::default();
+    sim.state(|s| {
+        s.unsupported.insert(Method::PinStatus);
+    });
+    let quest = connect(&sim, None).await.unwrap();
+
+    let e = quest.call_json(Method::PinStatus, None).await.unwrap_err();
+    assert_eq!(e.error_code(), Some(ErrorCode::UnsupportedMethod));
+}
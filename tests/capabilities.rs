@@ -0,0 +1,100 @@
+mod common;
+
+use common::{connect, requests};
+use hzospal::capabilities::Support;
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::set_dev_mode;
+use hzospal::simulator::{ErrorCode, Method, ResponseCode, SimulatedFailure, Simulator};
+
+#[tokio::test]
+async fn probes_what_the_firmware_answers() {
+    let sim = Simulator::default();
+    sim.state(|s| {
+        s.unsupported.insert(Method::DevModeStatus);
+    });
+    let quest = connect(&sim, None).await.unwrap();
+
+    let caps = quest.probe_capabilities().await.unwrap();
+    assert_eq!(caps.supports(Method::HmdStatus), Some(true));
+    assert_eq!(caps.supports(Method::DevModeStatus), Some(false));
+    // no way to read it back, so no way to set it
+    assert_eq!(caps.supports(Method::DevModeSet), Some(false));
+    // the simulator doesn't know PinStatus
+    assert_eq!(
+        caps.methods.get(&Method::PinStatus),
+        Some(&Support::Unsupported(ErrorCode::UnsupportedMethod))
+    );
+    assert_eq!(
+        caps.methods.get(&Method::OculusSecondaryUserAdd),
+        Some(&Support::Unavailable)
+    );
+    assert_eq!(caps.supports(Method::WifiConnect), None);
+}
+
+#[tokio::test]
+async fn refuses_unsupported_methods_without_asking() {
+    let sim = Simulator::default();
+    sim.state(|s| {
+        s.unsupported.insert(Method::DevModeStatus);
+    });
+    let quest = connect(&sim, None).await.unwrap();
+    quest.probe_capabilities().await.unwrap();
+
+    let before = sim.state(|s| s.requests.len());
+    let e = set_dev_mode(&quest, true).await.unwrap_err();
+    assert!(matches!(e, HzospalError::Unsupported(_)));
+    assert_eq!(sim.state(|s| s.requests.len()), before);
+}
+
+#[tokio::test]
+async fn probes_each_build_once() {
+    let sim = Simulator::default();
+    sim.state(|s| {
+        s.unsupported.insert(Method::DevModeStatus);
+    });
+    let quest = connect(&sim, None).await.unwrap();
+    quest.probe_capabilities().await.unwrap();
+
+    // only HMD_VERSION is asked again
+    let before = sim.state(|s| s.requests.len());
+    let versions = requests(&sim, Method::HmdVersion);
+    quest.probe_capabilities().await.unwrap();
+    assert_eq!(sim.state(|s| s.requests.len()), before + 1);
+    assert_eq!(requests(&sim, Method::HmdVersion), versions + 1);
+
+    sim.state(|s| {
+        s.hmd_version.version_incremental = Some("2".into());
+        s.unsupported.clear();
+    });
+    let caps = quest.probe_capabilities().await.unwrap();
+    assert_eq!(caps.version_incremental(), "2");
+    assert_eq!(caps.supports(Method::DevModeSet), None);
+
+    set_dev_mode(&quest, true).await.unwrap();
+    assert_eq!(
+        quest.capabilities().unwrap().supports(Method::DevModeSet),
+        Some(true)
+    );
+}
+
+#[tokio::test]
+async fn a_probe_answer_outranks_the_feature_flag() {
+    let sim = Simulator::default();
+    sim.state(|s| s.hmd_capabilities.chromecast_available = Some(false));
+    // failing for any reason but UNSUPPORTED_METHOD means the method exists
+    sim.fail_next(
+        Method::CastingStatus,
+        SimulatedFailure::new(ResponseCode::Fail, ErrorCode::InternalError),
+    );
+    let quest = connect(&sim, None).await.unwrap();
+
+    let caps = quest.probe_capabilities().await.unwrap();
+    assert_eq!(
+        caps.methods.get(&Method::CastingStatus),
+        Some(&Support::Supported)
+    );
+    assert_eq!(
+        caps.methods.get(&Method::StartCasting),
+        Some(&Support::Unavailable)
+    );
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{connect, connect_options, options};
+use hzospal::ConnectionOptions;
+use hzospal::error::HzospalError;
+use hzospal::protocol::certificate::CertificateVerification;
+use hzospal::simulator::Simulator;
+
+fn assert_untrusted(result: Result<hzospal::QuestDevice, HzospalError>) {
+    match result {
+        Err(HzospalError::Certificate(_)) => {}
+        Err(e) => panic!("expected a certificate error, got {e}"),
+        Ok(_) => panic!("connected to an untrusted headset"),
+    }
+}
+
+#[tokio::test]
+async fn trusts_a_chain_to_the_anchor() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    assert_eq!(quest.server_certificate, Some(sim.certificate()));
+}
+
+#[tokio::test]
+async fn refuses_without_anchors() {
+    let sim = Simulator::default();
+
+    assert_untrusted(connect_options(&sim, None, Default::default()).await);
+    assert_eq!(sim.state(|s| s.user_secret), None);
+}
+
+#[tokio::test]
+async fn refuses_a_chain_to_another_anchor() {
+    let sim = Simulator::default();
+    let other = Simulator::default();
+
+    assert_untrusted(connect_options(&sim, None, options(&other)).await);
+    assert_eq!(sim.state(|s| s.user_secret), None);
+}
+
+#[tokio::test]
+async fn refuses_a_forged_signature() {
+    let sim = Simulator::default();
+    sim.state(|s| s.forge_hello_signature = true);
+
+    assert_untrusted(connect(&sim, None).await);
+    assert_eq!(sim.state(|s| s.user_secret), None);
+}
+
+#[tokio::test]
+async fn refuses_certificates_issued_by_a_leaf() {
+    let sim = Simulator::default();
+    sim.issue_from_leaf();
+
+    assert_untrusted(connect(&sim, None).await);
+    assert_eq!(sim.state(|s| s.user_secret), None);
+}
+
+#[tokio::test]
+async fn insecure_trusts_anything_unpinned() {
+    let sim = Simulator::default();
+    sim.issue_from_leaf();
+    sim.state(|s| s.forge_hello_signature = true);
+
+    connect_options(
+        &sim,
+        None,
+        ConnectionOptions {
+            certificate_verification: CertificateVerification::Insecure,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[test]
+fn loads_anchors_from_der_and_pem() {
+    let sim = Simulator::default();
+    let der = sim.trust_anchor();
+
+    assert_eq!(
+        CertificateVerification::load_anchors(&der).unwrap(),
+        vec![der.clone()]
+    );
+
+    let pem = pem(&der);
+    let bundle = [pem.clone(), pem].concat();
+    assert_eq!(
+        CertificateVerification::load_anchors(bundle.as_bytes()).unwrap(),
+        vec![der.clone(), der]
+    );
+
+    assert!(CertificateVerification::load_anchors(b"not a certificate").is_err());
+}
+
+fn pem(der: &[u8]) -> String {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(64)
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect();
+    format!(
+        "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+        lines.join("\n")
+    )
+}
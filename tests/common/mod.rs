@@ -0,0 +1,62 @@
+// not every test uses every helper
+#![allow(dead_code)]
+
+use futures::FutureExt;
+use hzospal::error::HzospalError;
+use hzospal::protocol::certificate::CertificateVerification;
+use hzospal::session::Connector;
+use hzospal::simulator::Simulator;
+use hzospal::transport::QuestTransport;
+use hzospal::{ConnectionOptions, QuestDevice, connect_with_connector, connect_with_transport};
+use std::sync::Arc;
+
+// trusts the simulator's root and nothing else
+pub fn options(sim: &Simulator) -> ConnectionOptions {
+    ConnectionOptions {
+        certificate_verification: CertificateVerification::TrustAnchors(vec![sim.trust_anchor()]),
+        ..Default::default()
+    }
+}
+
+pub async fn connect(
+    sim: &Simulator,
+    device_key: Option<[u8; 32]>,
+) -> Result<QuestDevice, HzospalError> {
+    connect_options(sim, device_key, options(sim)).await
+}
+
+pub async fn connect_options(
+    sim: &Simulator,
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<QuestDevice, HzospalError> {
+    connect_with_transport(Box::new(sim.transport()), "sim".into(), device_key, options).await
+}
+
+// a fresh link to sim whenever it's reachable, like scanning for it again
+pub fn connector(sim: &Simulator) -> Connector {
+    let sim = sim.clone();
+    Arc::new(move || {
+        let sim = sim.clone();
+        async move {
+            if !sim.state(|s| s.connected) {
+                return Err(HzospalError::NotConnected);
+            }
+            Ok(Box::new(sim.transport()) as Box<dyn QuestTransport>)
+        }
+        .boxed()
+    })
+}
+
+pub async fn connect_reconnecting(
+    sim: &Simulator,
+    device_key: Option<[u8; 32]>,
+    options: ConnectionOptions,
+) -> Result<QuestDevice, HzospalError> {
+    connect_with_connector(connector(sim), "sim".into(), device_key, options).await
+}
+
+// how often the simulator was asked for method
+pub fn requests(sim: &Simulator, method: hzospal::simulator::Method) -> usize {
+    sim.state(|s| s.requests.iter().filter(|m| **m == method).count())
+}
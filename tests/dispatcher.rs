@@ -0,0 +1,116 @@
+mod common;
+
+use common::{connect, connect_options, options, requests};
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::{get_hmd_status, set_dev_mode, set_ota_mode};
+use hzospal::protocol::retry::RetryPolicy;
+use hzospal::simulator::{Method, Simulator};
+use hzospal::{ConnectionOptions, Timeouts};
+use std::time::Duration;
+
+// lets the other futures in a join run until the simulator saw count of method
+async fn wait_for_requests(sim: &Simulator, method: Method, count: usize) {
+    while requests(sim, method) < count {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+#[tokio::test]
+async fn concurrent_calls_get_their_own_responses() {
+    let sim = Simulator::default();
+    sim.state(|s| s.hmd_status.battery_level = Some(77));
+    let quest = connect(&sim, None).await.unwrap();
+
+    let (status, dev, ota) = tokio::join!(
+        get_hmd_status(&quest),
+        set_dev_mode(&quest, true),
+        set_ota_mode(&quest, false)
+    );
+
+    assert_eq!(status.unwrap().battery_level, Some(77));
+    dev.unwrap();
+    ota.unwrap();
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+    assert_eq!(sim.state(|s| s.ota_enabled.enabled), Some(false));
+}
+
+#[tokio::test]
+async fn concurrent_calls_while_polling() {
+    let sim = Simulator::default();
+    sim.state(|s| s.supports_notifications = false);
+    let quest = connect(&sim, None).await.unwrap();
+
+    let (status, dev, ota) = tokio::join!(
+        get_hmd_status(&quest),
+        set_dev_mode(&quest, true),
+        set_ota_mode(&quest, false)
+    );
+
+    status.unwrap();
+    dev.unwrap();
+    ota.unwrap();
+}
+
+#[tokio::test]
+async fn responses_out_of_order_reach_their_callers() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let asked = requests(&sim, Method::HmdStatus);
+    sim.state(|s| {
+        s.hold_responses = true;
+        s.hmd_status.battery_level = Some(10);
+    });
+
+    let (first, second) = tokio::join!(get_hmd_status(&quest), async {
+        wait_for_requests(&sim, Method::HmdStatus, asked + 1).await;
+        sim.state(|s| s.hmd_status.battery_level = Some(20));
+        let (second, ()) = tokio::join!(get_hmd_status(&quest), async {
+            wait_for_requests(&sim, Method::HmdStatus, asked + 2).await;
+            // the answer to the second request arrives first
+            sim.release_responses(true);
+        });
+        second
+    });
+
+    assert_eq!(first.unwrap().battery_level, Some(10));
+    assert_eq!(second.unwrap().battery_level, Some(20));
+}
+
+#[tokio::test]
+async fn a_late_response_is_dropped() {
+    let sim = Simulator::default();
+    let quest = connect_options(
+        &sim,
+        None,
+        ConnectionOptions {
+            retry_policy: RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            timeouts: Timeouts {
+                rpc: Duration::from_millis(200),
+                ..Default::default()
+            },
+            ..options(&sim)
+        },
+    )
+    .await
+    .unwrap();
+    let asked = requests(&sim, Method::HmdStatus);
+    sim.state(|s| {
+        s.hold_responses = true;
+        s.hmd_status.battery_level = Some(10);
+    });
+
+    let e = get_hmd_status(&quest).await.unwrap_err();
+    assert!(matches!(e, HzospalError::Timeout(_)), "{e}");
+
+    sim.state(|s| s.hmd_status.battery_level = Some(20));
+    let (status, ()) = tokio::join!(get_hmd_status(&quest), async {
+        wait_for_requests(&sim, Method::HmdStatus, asked + 2).await;
+        // the timed out request's answer arrives while the next one waits
+        sim.release_responses(false);
+    });
+
+    assert_eq!(status.unwrap().battery_level, Some(20));
+}
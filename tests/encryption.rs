@@ -0,0 +1,38 @@
+mod common;
+
+use common::connect;
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::get_hmd_status;
+use hzospal::simulator::Simulator;
+
+#[tokio::test]
+async fn refuses_plaintext_after_the_handshake() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    sim.state(|s| s.plaintext_responses = true);
+    let e = get_hmd_status(&quest).await.unwrap_err();
+    assert!(matches!(e, HzospalError::Crypto(_)), "{e}");
+
+    sim.state(|s| s.plaintext_responses = false);
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn ignores_replayed_responses() {
+    let sim = Simulator::default();
+    sim.state(|s| s.hmd_status.battery_level = Some(90));
+    let quest = connect(&sim, None).await.unwrap();
+
+    sim.state(|s| s.replay_responses = true);
+    assert_eq!(
+        get_hmd_status(&quest).await.unwrap().battery_level,
+        Some(90)
+    );
+    sim.state(|s| s.hmd_status.battery_level = Some(89));
+    // the copy of the first answer doesn't stand in for the second
+    assert_eq!(
+        get_hmd_status(&quest).await.unwrap().battery_level,
+        Some(89)
+    );
+}
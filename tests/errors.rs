@@ -0,0 +1,50 @@
+mod common;
+
+use common::connect;
+use hzospal::error::{ErrorCode, HzospalError, ResponseCode};
+use hzospal::protocol::functions::get_hmd_status;
+use hzospal::simulator::{Method, SimulatedFailure, Simulator};
+
+#[tokio::test]
+async fn failures_carry_the_error_details() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    sim.fail_next(
+        Method::HmdStatus,
+        SimulatedFailure {
+            debug_details: Some("battery at 2%".into()),
+            ..SimulatedFailure::new(ResponseCode::Fail, ErrorCode::BatteryTooLow)
+        },
+    );
+    let e = get_hmd_status(&quest).await.unwrap_err();
+
+    assert_eq!(e.error_code(), Some(ErrorCode::BatteryTooLow));
+    let HzospalError::Device(device) = &e else {
+        panic!("expected a device error, got {e}");
+    };
+    assert_eq!(device.response_code, ResponseCode::Fail);
+    assert_eq!(device.debug_details.as_deref(), Some("battery at 2%"));
+    assert!(e.to_string().contains("battery at 2%"));
+}
+
+#[tokio::test]
+async fn other_errors_have_no_code() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    sim.drop_link();
+    let e = get_hmd_status(&quest).await.unwrap_err();
+
+    assert!(matches!(e, HzospalError::NotConnected), "{e}");
+    assert_eq!(e.error_code(), None);
+}
+
+#[test]
+fn a_dropped_ble_link_is_not_connected() {
+    let e = HzospalError::from(btleplug::Error::NotConnected);
+    assert!(matches!(e, HzospalError::NotConnected), "{e}");
+
+    let e = HzospalError::from(btleplug::Error::DeviceNotFound);
+    assert!(matches!(e, HzospalError::Transport(_)), "{e}");
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::{connect, requests};
+use hzospal::keepalive::{KeepalivePolicy, spawn_keepalive};
+use hzospal::protocol::functions::ping;
+use hzospal::simulator::{Method, Simulator};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn ping_records_the_round_trip() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    let rtt = ping(&quest, Duration::from_secs(1)).await.unwrap();
+
+    let health = quest.health();
+    assert!(health.healthy);
+    assert_eq!(health.consecutive_failures, 0);
+    assert_eq!(health.last_rtt, Some(rtt));
+    assert!(health.last_success.is_some());
+}
+
+#[tokio::test]
+async fn keepalive_pings_and_flags_a_dead_link() {
+    let sim = Simulator::default();
+    let quest = Arc::new(connect(&sim, None).await.unwrap());
+
+    let _keepalive = spawn_keepalive(
+        &quest,
+        KeepalivePolicy {
+            interval: Duration::from_millis(100),
+            reconnect_after: None,
+            ..Default::default()
+        },
+    );
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    assert!(requests(&sim, Method::Ping) >= 2);
+    assert!(quest.health().healthy);
+
+    sim.drop_link();
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    let health = quest.health();
+    assert!(!health.healthy);
+    assert!(health.consecutive_failures >= 2);
+    assert!(health.last_failure.is_some());
+}
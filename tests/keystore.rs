@@ -0,0 +1,196 @@
+mod common;
+
+use common::{connect, connect_options, options};
+use hzospal::ConnectionOptions;
+use hzospal::error::HzospalError;
+use hzospal::keystore::{HeadsetIdentity, KeyEntry, KeyStore};
+use hzospal::protocol::functions::get_hmd_status;
+use hzospal::simulator::Simulator;
+use std::path::PathBuf;
+
+// unique per test process, removed again by the test that made it
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("hzospal-test-{}-{}", std::process::id(), name))
+}
+
+#[tokio::test]
+async fn keeps_a_key_per_headset() {
+    let a = Simulator::default();
+    let b = Simulator::default();
+    let mut store = KeyStore::default();
+
+    for sim in [&a, &b] {
+        let quest = connect(sim, None).await.unwrap();
+        let status = get_hmd_status(&quest).await.unwrap();
+        store.remember(&quest, Some(&status)).unwrap();
+    }
+    assert_eq!(store.headsets.len(), 2);
+
+    let path = temp_path("keys.json");
+    store.save(&path).unwrap();
+    let loaded = KeyStore::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, store);
+
+    // each headset gets its own key back after hello
+    for sim in [&b, &a] {
+        sim.reset_session();
+        let quest = connect_options(
+            sim,
+            None,
+            ConnectionOptions {
+                keystore: Some(loaded.clone()),
+                ..options(sim)
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(quest.device_key, sim.state(|s| s.user_secret));
+        get_hmd_status(&quest).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn status_fills_in_the_entry_remembered_before_it() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let mut store = KeyStore::default();
+
+    // what the CLI saves straight after claiming
+    store.remember(&quest, None).unwrap();
+    let status = get_hmd_status(&quest).await.unwrap();
+    store.remember(&quest, Some(&status)).unwrap();
+
+    assert_eq!(store.headsets.len(), 1);
+    let entry = &store.headsets[0];
+    assert_eq!(Some(entry.user_secret), quest.device_key);
+    assert_eq!(entry.identity.device_id, status.device_id);
+    assert_eq!(entry.identity.provisioned_serial, status.provisioned_serial);
+    assert!(entry.matches(status.provisioned_serial.as_deref().unwrap()));
+}
+
+#[test]
+fn a_missing_file_is_an_empty_store() {
+    let path = temp_path("missing.json");
+
+    assert_eq!(KeyStore::load(&path).unwrap(), KeyStore::default());
+}
+
+#[tokio::test]
+async fn encrypts_with_a_passphrase() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let mut store = KeyStore::default();
+    store.remember(&quest, None).unwrap();
+    let key = quest.device_key.unwrap();
+
+    let path = temp_path("keys.enc");
+    store.save_encrypted(&path, "hunter2").unwrap();
+    let data = std::fs::read(&path).unwrap();
+    let loaded = KeyStore::load_encrypted(&path, "hunter2");
+    let wrong = KeyStore::load_encrypted(&path, "hunter3");
+    let plain = KeyStore::load(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!data.windows(key.len()).any(|w| w == key));
+    assert!(
+        !data
+            .windows(hex::encode(key).len())
+            .any(|w| w == hex::encode(key).as_bytes())
+    );
+    assert_eq!(loaded.unwrap(), store);
+    assert!(wrong.is_err());
+    assert!(plain.is_err());
+}
+
+#[test]
+fn refuses_a_tampered_store() {
+    let store = KeyStore::default();
+    let path = temp_path("tampered.enc");
+    store.save_encrypted(&path, "hunter2").unwrap();
+
+    let mut data = std::fs::read(&path).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    let ciphertext = KeyStore::load_encrypted(&path, "hunter2");
+
+    // the header is authenticated too
+    data[last] ^= 1;
+    data[8] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    let header = KeyStore::load_encrypted(&path, "hunter2");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(ciphertext.is_err());
+    assert!(header.is_err());
+}
+
+#[test]
+fn refuses_kdf_parameters_that_would_hang() {
+    let store = KeyStore::default();
+    let path = temp_path("expensive.enc");
+    store.save_encrypted(&path, "hunter2").unwrap();
+    let data = std::fs::read(&path).unwrap();
+
+    // memory, iterations and lanes follow the magic, version and KDF
+    let mut refused = Vec::new();
+    for offset in [6, 10, 14] {
+        let mut data = data.clone();
+        data[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        refused.push(KeyStore::load_encrypted(&path, "hunter2").is_err());
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(refused, [true; 3]);
+}
+
+#[test]
+fn refuses_a_key_whose_certificate_contradicts_the_headset() {
+    let mut store = KeyStore::default();
+    store
+        .import(KeyEntry {
+            nickname: None,
+            identity: HeadsetIdentity {
+                address: Some("01:02:03:04:05:06".into()),
+                certificate_fingerprint: Some([1; 32]),
+                ..Default::default()
+            },
+            user_secret: [7; 32],
+            pending_secret: None,
+            certificate: None,
+            claimed_at: 0,
+        })
+        .unwrap();
+
+    let presenting = |fingerprint| HeadsetIdentity {
+        address: Some("01:02:03:04:05:06".into()),
+        certificate_fingerprint: fingerprint,
+        ..Default::default()
+    };
+
+    assert!(
+        store
+            .find_checked(&presenting(Some([1; 32])), false)
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        store
+            .find_checked(&presenting(None), false)
+            .unwrap()
+            .is_some()
+    );
+    // the same address, but another certificate
+    assert!(matches!(
+        store.find_checked(&presenting(Some([2; 32])), false),
+        Err(HzospalError::Certificate(_))
+    ));
+    assert!(
+        store
+            .find_checked(&presenting(Some([2; 32])), true)
+            .unwrap()
+            .is_some()
+    );
+}
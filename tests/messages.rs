@@ -0,0 +1,68 @@
+#![cfg(feature = "serde")]
+
+use hzospal::messages::{ControllerScanRequest, HmdStatusResponse, Method, NuxStatus, Request};
+use serde_json::json;
+
+#[test]
+fn round_trips_through_json() {
+    let status = HmdStatusResponse {
+        battery_level: Some(50),
+        nux_status: Some(NuxStatus::try_from(1).unwrap().into()),
+        ..Default::default()
+    };
+
+    let value = serde_json::to_value(&status).unwrap();
+    assert_eq!(value["battery_level"], json!(50));
+    // fields that aren't set are left out
+    assert!(value.get("charging").is_none());
+    assert_eq!(
+        serde_json::from_value::<HmdStatusResponse>(value).unwrap(),
+        status
+    );
+}
+
+#[test]
+fn enums_use_their_proto_names() {
+    assert_eq!(
+        serde_json::to_value(Method::HmdStatus).unwrap(),
+        json!("HMD_STATUS")
+    );
+
+    let request: Request =
+        serde_json::from_value(json!({"method": "HMD_VERSION", "seq": 3})).unwrap();
+    assert_eq!(request.method(), Method::HmdVersion);
+    assert_eq!(
+        serde_json::to_value(&request).unwrap()["method"],
+        json!("HMD_VERSION")
+    );
+
+    // the number works too
+    let request: Request = serde_json::from_value(json!({"method": 8002})).unwrap();
+    assert_eq!(request.method(), Method::HmdVersion);
+
+    assert!(serde_json::from_value::<Request>(json!({"method": "NOPE"})).is_err());
+}
+
+#[test]
+fn keeps_enum_values_it_doesnt_know() {
+    let status = HmdStatusResponse {
+        headset_state: Some(4242),
+        ..Default::default()
+    };
+    let value = serde_json::to_value(&status).unwrap();
+    assert_eq!(value["headset_state"], json!(4242));
+    assert_eq!(
+        serde_json::from_value::<HmdStatusResponse>(value).unwrap(),
+        status
+    );
+
+    let request = ControllerScanRequest {
+        types: vec![1, 2, 77],
+    };
+    let value = serde_json::to_value(&request).unwrap();
+    assert_eq!(value["types"][2], json!(77));
+    assert_eq!(
+        serde_json::from_value::<ControllerScanRequest>(value).unwrap(),
+        request
+    );
+}
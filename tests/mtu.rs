@@ -0,0 +1,107 @@
+mod common;
+
+use common::{connect, connect_options, options};
+use hzospal::ConnectionOptions;
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::{WifiAuthentication, connect_to_wifi, get_hmd_status, skip_nux};
+use hzospal::simulator::Simulator;
+
+#[tokio::test]
+async fn uses_the_negotiated_mtu() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    assert_eq!(quest.mtu(), 247);
+    skip_nux(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn fragments_for_the_minimum_mtu() {
+    let sim = Simulator::default();
+    sim.state(|s| s.mtu = 23);
+    let quest = connect(&sim, None).await.unwrap();
+
+    assert_eq!(quest.mtu(), 23);
+    // the longest request we send, many fragments at 20 bytes each
+    skip_nux(&quest).await.unwrap();
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn override_wins_over_the_link() {
+    let sim = Simulator::default();
+    let quest = connect_options(
+        &sim,
+        None,
+        ConnectionOptions {
+            mtu: Some(23),
+            ..options(&sim)
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(quest.mtu(), 23);
+    skip_nux(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn fragments_too_large_for_the_link_fail() {
+    let sim = Simulator::default();
+    sim.state(|s| s.mtu = 23);
+
+    let result = connect_options(
+        &sim,
+        None,
+        ConnectionOptions {
+            mtu: Some(512),
+            ..options(&sim)
+        },
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn fragments_fit_an_attribute_at_large_mtus() {
+    let sim = Simulator::default();
+    // what BlueZ commonly negotiates
+    sim.state(|s| s.mtu = 517);
+    let quest = connect(&sim, None).await.unwrap();
+
+    assert_eq!(quest.mtu(), 517);
+    // long enough to need full fragments
+    let password = "x".repeat(2000);
+    connect_to_wifi(&quest, "home".into(), password, WifiAuthentication::Wpa)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn refuses_an_override_below_the_minimum() {
+    let sim = Simulator::default();
+
+    for mtu in [0, 5, 22] {
+        let result = connect_options(
+            &sim,
+            None,
+            ConnectionOptions {
+                mtu: Some(mtu),
+                ..options(&sim)
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(HzospalError::Framing(_))));
+    }
+    assert!(sim.state(|s| s.requests.is_empty()));
+}
+
+#[tokio::test]
+async fn a_link_mtu_below_the_minimum_falls_back_to_it() {
+    let sim = Simulator::default();
+    sim.state(|s| s.mtu = 2);
+
+    // the simulator refuses the fragments instead of waiting for them
+    let result = connect(&sim, None).await;
+    assert!(matches!(result, Err(HzospalError::Transport(_))));
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{connect_options, options};
+use hzospal::ConnectionOptions;
+use hzospal::error::HzospalError;
+use hzospal::protocol::certificate::CertificateVerification;
+use hzospal::simulator::Simulator;
+
+fn insecure(pinned: Option<Vec<u8>>, accept_changed: bool) -> ConnectionOptions {
+    ConnectionOptions {
+        certificate_verification: CertificateVerification::Insecure,
+        pinned_certificate: pinned,
+        accept_changed_certificate: accept_changed,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn pins_on_first_use() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, insecure(None, false))
+        .await
+        .unwrap();
+    assert_eq!(quest.server_certificate, Some(sim.certificate()));
+
+    // the headset recognises the fingerprint and leaves the certificate out
+    let pinned = quest.server_certificate.clone();
+    let quest = connect_options(&sim, quest.device_key, insecure(pinned.clone(), false))
+        .await
+        .unwrap();
+    assert_eq!(quest.server_certificate, pinned);
+
+    let quest = connect_options(
+        &sim,
+        quest.device_key,
+        ConnectionOptions {
+            pinned_certificate: pinned.clone(),
+            ..options(&sim)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(quest.server_certificate, pinned);
+}
+
+#[tokio::test]
+async fn refuses_a_changed_certificate() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, insecure(None, false))
+        .await
+        .unwrap();
+    let pinned = quest.server_certificate.clone();
+
+    sim.rotate_certificate();
+    let e = connect_options(&sim, quest.device_key, insecure(pinned, false))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(e, HzospalError::Certificate(_)), "{e}");
+}
+
+#[tokio::test]
+async fn accepts_a_changed_certificate_when_told_to() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, insecure(None, false))
+        .await
+        .unwrap();
+    let pinned = quest.server_certificate.clone();
+
+    sim.rotate_certificate();
+    let quest = connect_options(&sim, quest.device_key, insecure(pinned, true))
+        .await
+        .unwrap();
+    assert_eq!(quest.server_certificate, Some(sim.certificate()));
+}
+
+#[tokio::test]
+async fn the_pin_holds_even_when_insecure() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, insecure(None, false))
+        .await
+        .unwrap();
+    let pinned = quest.server_certificate.clone();
+
+    // an impostor who knows the pinned certificate but not its key
+    sim.state(|s| s.forge_hello_signature = true);
+    let e = connect_options(&sim, quest.device_key, insecure(pinned, false))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(e, HzospalError::Certificate(_)), "{e}");
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::connect;
+use hzospal::protocol::functions::{get_hmd_status, set_dev_mode};
+use hzospal::simulator::Simulator;
+
+#[tokio::test]
+async fn receives_by_notification() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    get_hmd_status(&quest).await.unwrap();
+    set_dev_mode(&quest, true).await.unwrap();
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+}
+
+#[tokio::test]
+async fn polls_without_notifications() {
+    let sim = Simulator::default();
+    sim.state(|s| s.supports_notifications = false);
+    let quest = connect(&sim, None).await.unwrap();
+
+    get_hmd_status(&quest).await.unwrap();
+    set_dev_mode(&quest, true).await.unwrap();
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{connect, connect_reconnecting, options, requests};
+use hzospal::ConnectionOptions;
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::{get_hmd_status, set_dev_mode};
+use hzospal::session::ReconnectPolicy;
+use hzospal::simulator::{Method, Simulator};
+use std::time::Duration;
+
+fn quick(sim: &Simulator) -> ConnectionOptions {
+    ConnectionOptions {
+        reconnect_policy: ReconnectPolicy {
+            initial_backoff: Duration::from_millis(50),
+            ..Default::default()
+        },
+        ..options(sim)
+    }
+}
+
+#[tokio::test]
+async fn reconnects_once_the_headset_is_back() {
+    let sim = Simulator::default();
+    let quest = connect_reconnecting(&sim, None, quick(&sim)).await.unwrap();
+    get_hmd_status(&quest).await.unwrap();
+
+    sim.drop_link();
+    let back = sim.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        back.state(|s| s.connected = true);
+    });
+
+    get_hmd_status(&quest).await.unwrap();
+    set_dev_mode(&quest, true).await.unwrap();
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+    // authenticated again with the key it claimed with
+    assert_eq!(requests(&sim, Method::Hello), 2);
+    assert_eq!(requests(&sim, Method::Authenticate), 1);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let sim = Simulator::default();
+    let quest = connect_reconnecting(
+        &sim,
+        None,
+        ConnectionOptions {
+            reconnect_policy: ReconnectPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..options(&sim)
+        },
+    )
+    .await
+    .unwrap();
+
+    sim.drop_link();
+    assert!(get_hmd_status(&quest).await.is_err());
+}
+
+#[tokio::test]
+async fn without_a_connector_a_drop_is_final() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    sim.drop_link();
+    let e = get_hmd_status(&quest).await.unwrap_err();
+    assert!(matches!(e, HzospalError::NotConnected), "{e}");
+}
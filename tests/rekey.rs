@@ -0,0 +1,96 @@
+mod common;
+
+use common::{connect, options};
+use futures::FutureExt;
+use hzospal::error::{ErrorCode, HzospalError, ResponseCode};
+use hzospal::keystore::KeyStore;
+use hzospal::protocol::functions::{get_hmd_status, rekey};
+use hzospal::session::Connector;
+use hzospal::simulator::{Method, SimulatedFailure, Simulator};
+use hzospal::transport::QuestTransport;
+use hzospal::{ConnectionOptions, QuestDevice, connect_with_connector};
+use std::sync::Arc;
+
+// rekey hangs up to prove the new key works, the headset stays in range and
+// takes the next link
+async fn connect_in_range(
+    sim: &Simulator,
+    options: ConnectionOptions,
+) -> Result<QuestDevice, HzospalError> {
+    let in_range = sim.clone();
+    let connector: Connector = Arc::new(move || {
+        let sim = in_range.clone();
+        async move {
+            sim.state(|s| s.connected = true);
+            Ok(Box::new(sim.transport()) as Box<dyn QuestTransport>)
+        }
+        .boxed()
+    });
+    connect_with_connector(connector, "sim".into(), None, options).await
+}
+
+#[tokio::test]
+async fn installs_the_new_key() {
+    let sim = Simulator::default();
+    let mut quest = connect_in_range(&sim, options(&sim)).await.unwrap();
+
+    rekey(&mut quest, [7; 32]).await.unwrap();
+
+    assert_eq!(quest.device_key, Some([7; 32]));
+    assert_eq!(sim.state(|s| s.user_secret), Some([7; 32]));
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn keeps_the_old_key_when_refused() {
+    let sim = Simulator::default();
+    let mut quest = connect_in_range(&sim, options(&sim)).await.unwrap();
+    let old = quest.device_key;
+
+    sim.fail_next(
+        Method::OculusSetUserSecret,
+        SimulatedFailure::new(ResponseCode::Fail, ErrorCode::BadArguement),
+    );
+    let e = rekey(&mut quest, [9; 32]).await.unwrap_err();
+
+    assert_eq!(e.error_code(), Some(ErrorCode::BadArguement));
+    assert_eq!(quest.device_key, old);
+    assert_eq!(sim.state(|s| s.user_secret), old);
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn needs_a_link_it_can_reconnect() {
+    let sim = Simulator::default();
+    let mut quest = connect(&sim, None).await.unwrap();
+    let old = quest.device_key;
+
+    let e = rekey(&mut quest, [7; 32]).await.unwrap_err();
+
+    assert!(matches!(e, HzospalError::Protocol(_)), "{e}");
+    assert_eq!(sim.state(|s| s.user_secret), old);
+}
+
+#[tokio::test]
+async fn an_interrupted_rekey_finds_the_pending_key() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let mut store = KeyStore::default();
+    store.remember(&quest, None).unwrap();
+
+    // the headset took the new key but we never heard back
+    sim.state(|s| s.user_secret = Some([7; 32]));
+    store.headsets[0].pending_secret = Some([7; 32]);
+
+    sim.reset_session();
+    let quest = connect_in_range(
+        &sim,
+        ConnectionOptions {
+            keystore: Some(store),
+            ..options(&sim)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(quest.device_key, Some([7; 32]));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::{connect_options, options, requests};
+use hzospal::ConnectionOptions;
+use hzospal::error::{ErrorCode, ResponseCode};
+use hzospal::protocol::functions::{get_hmd_status, set_dev_mode};
+use hzospal::protocol::retry::RetryPolicy;
+use hzospal::simulator::{Method, SimulatedFailure, Simulator};
+use std::time::Duration;
+
+fn quick(sim: &Simulator) -> ConnectionOptions {
+    ConnectionOptions {
+        retry_policy: RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        },
+        ..options(sim)
+    }
+}
+
+#[tokio::test]
+async fn retries_fail_retry_and_retryable_codes() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, quick(&sim)).await.unwrap();
+
+    sim.fail_next(
+        Method::DevModeSet,
+        SimulatedFailure::new(ResponseCode::FailRetry, ErrorCode::InternalError),
+    );
+    sim.fail_next(
+        Method::DevModeSet,
+        SimulatedFailure::new(ResponseCode::Fail, ErrorCode::AlreadyInProgress),
+    );
+    set_dev_mode(&quest, true).await.unwrap();
+
+    assert_eq!(requests(&sim, Method::DevModeSet), 3);
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, quick(&sim)).await.unwrap();
+
+    for _ in 0..3 {
+        sim.fail_next(
+            Method::HmdStatus,
+            SimulatedFailure::new(ResponseCode::FailRetry, ErrorCode::InternalError),
+        );
+    }
+    let e = get_hmd_status(&quest).await.unwrap_err();
+
+    assert_eq!(e.error_code(), Some(ErrorCode::InternalError));
+    assert_eq!(requests(&sim, Method::HmdStatus), 3);
+}
+
+#[tokio::test]
+async fn doesnt_retry_plain_failures() {
+    let sim = Simulator::default();
+    let quest = connect_options(&sim, None, quick(&sim)).await.unwrap();
+
+    sim.fail_next(
+        Method::HmdStatus,
+        SimulatedFailure::new(ResponseCode::Fail, ErrorCode::BatteryTooLow),
+    );
+    let e = get_hmd_status(&quest).await.unwrap_err();
+
+    assert_eq!(e.error_code(), Some(ErrorCode::BatteryTooLow));
+    assert_eq!(requests(&sim, Method::HmdStatus), 1);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::connect;
+use hzospal::messages::{DevModeRequest, Method};
+use hzospal::protocol::rpc::{self, MethodVisitor, QuestRpc, visit};
+use hzospal::simulator::Simulator;
+
+#[tokio::test]
+async fn calls_decode_into_the_method_response() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    let version = quest.call::<rpc::HmdVersion>(()).await.unwrap();
+    assert_eq!(version.model.as_deref(), Some("Quest 3"));
+
+    quest
+        .call::<rpc::DevModeSet>(DevModeRequest { mode: Some(1) })
+        .await
+        .unwrap();
+    let dev = quest.call::<rpc::DevModeStatus>(()).await.unwrap();
+    assert_eq!(dev.status, Some(1));
+}
+
+struct MethodOf;
+
+impl MethodVisitor for MethodOf {
+    type Output = (Method, bool);
+
+    fn visit<M: QuestRpc>(self) -> Self::Output {
+        (M::METHOD, M::SENDS_BODY)
+    }
+}
+
+#[test]
+fn every_method_has_a_type() {
+    for value in 0..1_000_000 {
+        let Ok(method) = Method::try_from(value) else {
+            continue;
+        };
+        match visit(method, MethodOf) {
+            Some((visited, _)) => assert_eq!(visited, method),
+            None => assert_eq!(method, Method::Unknown),
+        }
+    }
+
+    assert_eq!(
+        visit(Method::HmdStatus, MethodOf),
+        Some((Method::HmdStatus, false))
+    );
+    assert_eq!(
+        visit(Method::DevModeSet, MethodOf),
+        Some((Method::DevModeSet, true))
+    );
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::connect;
+use hzospal::protocol::functions::{get_hmd_status, set_dev_mode, skip_nux};
+use hzospal::simulator::Simulator;
+
+#[tokio::test]
+async fn claims_an_unclaimed_headset() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+
+    let key = quest.device_key.unwrap();
+    assert_eq!(sim.state(|s| s.user_secret), Some(key));
+}
+
+#[tokio::test]
+async fn reports_and_changes_settings() {
+    let sim = Simulator::default();
+    sim.state(|s| s.hmd_status.battery_level = Some(64));
+    let quest = connect(&sim, None).await.unwrap();
+
+    let status = get_hmd_status(&quest).await.unwrap();
+    assert_eq!(status.battery_level, Some(64));
+
+    set_dev_mode(&quest, true).await.unwrap();
+    assert_eq!(sim.state(|s| s.dev_mode.status), Some(1));
+    assert_eq!(sim.state(|s| s.hmd_status.developer_mode), Some(true));
+
+    skip_nux(&quest).await.unwrap();
+    assert_eq!(sim.state(|s| s.hmd_status.nux_completed), Some(true));
+}
+
+#[tokio::test]
+async fn authenticates_with_the_claimed_key() {
+    let key = [3; 32];
+    let sim = Simulator::claimed(key);
+
+    let quest = connect(&sim, Some(key)).await.unwrap();
+    assert_eq!(quest.device_key, Some(key));
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[tokio::test]
+async fn refuses_the_wrong_key() {
+    let sim = Simulator::claimed([3; 32]);
+
+    assert!(connect(&sim, Some([4; 32])).await.is_err());
+    assert_eq!(sim.state(|s| s.user_secret), Some([3; 32]));
+}
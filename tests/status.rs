@@ -0,0 +1,72 @@
+mod common;
+
+use common::connect;
+use futures::StreamExt;
+use hzospal::simulator::{HmdStatusResponse, HmdVersionResponse, Simulator};
+use hzospal::status::{StatusEvent, status_events};
+use prost::Message;
+
+#[tokio::test]
+async fn streams_status_pushes() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let mut events = status_events(&quest).await.unwrap();
+
+    sim.state(|s| s.hmd_status.battery_level = Some(42));
+    sim.push_status().unwrap();
+    sim.state(|s| s.hmd_status.battery_level = Some(41));
+    sim.push_status().unwrap();
+
+    let StatusEvent::HmdStatus(status) = events.next().await.unwrap() else {
+        panic!("expected a status");
+    };
+    assert_eq!(status.battery_level, Some(42));
+    let StatusEvent::HmdStatus(status) = events.next().await.unwrap() else {
+        panic!("expected a status");
+    };
+    assert_eq!(status.battery_level, Some(41));
+}
+
+#[tokio::test]
+async fn undecodable_pushes_stay_raw() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let mut events = status_events(&quest).await.unwrap();
+
+    sim.push_raw_status(b"hello");
+    assert_eq!(
+        events.next().await.unwrap(),
+        StatusEvent::Raw(b"hello".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn pushes_that_arent_a_status_stay_raw() {
+    let sim = Simulator::default();
+    let quest = connect(&sim, None).await.unwrap();
+    let mut events = status_events(&quest).await.unwrap();
+
+    // fields HmdStatusResponse doesn't have
+    let version = HmdVersionResponse {
+        display: Some("UP1A.231005.007.A1".into()),
+        manufacturer: Some("Oculus".into()),
+        ..Default::default()
+    }
+    .encode_to_vec();
+    sim.push_status_body(version.clone()).unwrap();
+
+    // a status with one of them tacked on
+    let status = HmdStatusResponse {
+        battery_level: Some(42),
+        ..Default::default()
+    };
+    sim.push_status_body([status.encode_to_vec(), version].concat())
+        .unwrap();
+
+    // no field at all
+    sim.push_status_body(Vec::new()).unwrap();
+
+    for _ in 0..3 {
+        assert!(matches!(events.next().await.unwrap(), StatusEvent::Raw(_)));
+    }
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use async_trait::async_trait;
+use common::{connect, options};
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::{get_hmd_status, set_dev_mode, set_ota_mode};
+use hzospal::protocol::retry::RetryPolicy;
+use hzospal::simulator::{Method, Simulator, SimulatorTransport};
+use hzospal::transport::{FragmentStream, QuestTransport};
+use hzospal::{ConnectionOptions, Timeouts, connect_with_transport};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// a headset that stops answering once muted is set
+struct Mutable {
+    inner: SimulatorTransport,
+    muted: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl QuestTransport for Mutable {
+    async fn write_fragment(&self, fragment: &[u8]) -> Result<(), HzospalError> {
+        if self.muted.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.inner.write_fragment(fragment).await
+    }
+
+    async fn read_fragment(&self) -> Result<Vec<u8>, HzospalError> {
+        self.inner.read_fragment().await
+    }
+
+    fn supports_notifications(&self) -> bool {
+        self.inner.supports_notifications()
+    }
+
+    async fn subscribe(&self) -> Result<FragmentStream, HzospalError> {
+        self.inner.subscribe().await
+    }
+
+    async fn is_connected(&self) -> Result<bool, HzospalError> {
+        self.inner.is_connected().await
+    }
+
+    async fn disconnect(&self) -> Result<(), HzospalError> {
+        self.inner.disconnect().await
+    }
+}
+
+#[tokio::test]
+async fn times_out_when_the_headset_doesnt_answer() {
+    let sim = Simulator::default();
+    let muted = Arc::new(AtomicBool::new(false));
+    let transport = Mutable {
+        inner: sim.transport(),
+        muted: muted.clone(),
+    };
+    let quest = connect_with_transport(
+        Box::new(transport),
+        "sim".into(),
+        None,
+        ConnectionOptions {
+            timeouts: Timeouts {
+                rpc: Duration::from_millis(100),
+                ..Default::default()
+            },
+            retry_policy: RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            ..options(&sim)
+        },
+    )
+    .await
+    .unwrap();
+
+    muted.store(true, Ordering::SeqCst);
+    let e = get_hmd_status(&quest).await.unwrap_err();
+    assert!(matches!(e, HzospalError::Timeout(_)), "{e}");
+
+    // the timed out request left nothing behind to trip up the next one
+    muted.store(false, Ordering::SeqCst);
+    get_hmd_status(&quest).await.unwrap();
+}
+
+#[test]
+fn per_method_timeouts_override_the_default() {
+    let timeouts = Timeouts::default();
+
+    assert_eq!(
+        timeouts.for_method(Method::WifiConnect),
+        Duration::from_secs(90)
+    );
+    assert_eq!(timeouts.for_method(Method::HmdStatus), timeouts.rpc);
+}
+
+#[tokio::test]
+async fn dropping_a_call_leaves_the_session_usable() {
+    let sim = Simulator::default();
+    sim.state(|s| s.supports_notifications = false);
+    let quest = connect(&sim, None).await.unwrap();
+
+    {
+        let mut call = Box::pin(set_dev_mode(&quest, true));
+        assert!(futures::poll!(&mut call).is_pending());
+        tokio::task::yield_now().await;
+        assert!(futures::poll!(&mut call).is_pending());
+    }
+
+    set_ota_mode(&quest, false).await.unwrap();
+    get_hmd_status(&quest).await.unwrap();
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use async_trait::async_trait;
+use hzospal::connect_with_transport;
+use hzospal::error::HzospalError;
+use hzospal::protocol::functions::get_hmd_status;
+use hzospal::simulator::{Simulator, SimulatorTransport};
+use hzospal::transport::{FragmentStream, QuestTransport};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// any link will do, this one only counts what goes over it
+struct Counting {
+    inner: SimulatorTransport,
+    written: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl QuestTransport for Counting {
+    async fn write_fragment(&self, fragment: &[u8]) -> Result<(), HzospalError> {
+        self.written.fetch_add(1, Ordering::SeqCst);
+        self.inner.write_fragment(fragment).await
+    }
+
+    async fn read_fragment(&self) -> Result<Vec<u8>, HzospalError> {
+        self.inner.read_fragment().await
+    }
+
+    fn supports_notifications(&self) -> bool {
+        self.inner.supports_notifications()
+    }
+
+    async fn subscribe(&self) -> Result<FragmentStream, HzospalError> {
+        self.inner.subscribe().await
+    }
+
+    async fn is_connected(&self) -> Result<bool, HzospalError> {
+        self.inner.is_connected().await
+    }
+
+    async fn disconnect(&self) -> Result<(), HzospalError> {
+        self.inner.disconnect().await
+    }
+}
+
+#[tokio::test]
+async fn speaks_the_protocol_over_any_transport() {
+    let sim = Simulator::default();
+    let written = Arc::new(AtomicUsize::new(0));
+    let transport = Counting {
+        inner: sim.transport(),
+        written: written.clone(),
+    };
+
+    let quest = connect_with_transport(
+        Box::new(transport),
+        "sim".into(),
+        None,
+        common::options(&sim),
+    )
+    .await
+    .unwrap();
+    let handshake = written.load(Ordering::SeqCst);
+    assert!(handshake > 0);
+
+    get_hmd_status(&quest).await.unwrap();
+    assert!(written.load(Ordering::SeqCst) > handshake);
+}